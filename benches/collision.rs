@@ -0,0 +1,97 @@
+//! Compares the broad-phase grid with testing every collider of the level, as the collision
+//! check did before the grid. Run with `cargo bench --bench collision`.
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+// only the grid is benchmarked, and Bevy has complex types
+#[allow(dead_code, clippy::type_complexity)]
+#[path = "../src/collision.rs"]
+mod collision;
+
+use collision::CollisionGrid;
+
+/// Same gaps and radii as the level generator, over much longer levels
+const MIN_OBSTACLE_GAP: f32 = 2.5;
+const MAX_OBSTACLE_GAP: f32 = 7.5;
+const SPHERE_RADIUS: f32 = 0.5;
+/// Distance the player moves on one tick at the starting scroll speed
+const TICK_DISTANCE: f32 = 0.1;
+
+fn level(obstacle_count: u32) -> Vec<(Entity, Vec2)> {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mut x = 0.0;
+    (0..obstacle_count)
+        .map(|i| {
+            x += rng.gen_range(MIN_OBSTACLE_GAP..MAX_OBSTACLE_GAP);
+            (Entity::from_raw(i), Vec2::new(x, 0.0))
+        })
+        .collect()
+}
+
+// Counts the colliders touching the player at each tick of a run through the whole level
+fn full_scan(colliders: &[(Entity, Vec2)], end: f32) -> u32 {
+    let mut hits = 0;
+    let mut x = 0.0;
+    while x < end {
+        let player = Vec2::new(x, 0.0);
+        for (_, position) in colliders {
+            if position.distance(player) <= 2.0 * SPHERE_RADIUS {
+                hits += 1;
+            }
+        }
+        x += TICK_DISTANCE;
+    }
+    hits
+}
+
+fn grid_scan(grid: &CollisionGrid, positions: &HashMap<Entity, Vec2>, end: f32) -> u32 {
+    let mut hits = 0;
+    let mut x = 0.0;
+    while x < end {
+        let player = Vec2::new(x, 0.0);
+        for entity in grid.nearby(x, 2.0 * SPHERE_RADIUS) {
+            // stands in for the collider query lookup
+            if positions[&entity].distance(player) <= 2.0 * SPHERE_RADIUS {
+                hits += 1;
+            }
+        }
+        x += TICK_DISTANCE;
+    }
+    hits
+}
+
+fn collision_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collision");
+    for obstacle_count in [40, 400, 4000] {
+        let colliders = level(obstacle_count);
+        let end = colliders.last().map_or(0.0, |(_, position)| position.x);
+        let mut grid = CollisionGrid::default();
+        for (entity, position) in &colliders {
+            grid.insert(*entity, position.x);
+        }
+        let positions: HashMap<Entity, Vec2> = colliders.iter().copied().collect();
+        assert_eq!(
+            full_scan(&colliders, end),
+            grid_scan(&grid, &positions, end)
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("full_scan", obstacle_count),
+            &colliders,
+            |b, colliders| b.iter(|| full_scan(black_box(colliders), end)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("grid", obstacle_count),
+            &grid,
+            |b, grid| b.iter(|| grid_scan(black_box(grid), &positions, end)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, collision_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+
+use crate::tuning::Tuning;
+
+/// Accumulates frame time into fixed simulation ticks of `Tuning::time_step` seconds.
+/// Works like Bevy's `FixedTimestep`, except that the step can change while the game runs.
+pub struct SimClock {
+    accumulator: f64,
+    looping: bool,
+    /// Time does not pass for the simulation while paused
+    pub paused: bool,
+    /// Speed of the simulation relative to real time
+    pub time_scale: f32,
+    /// Ticks to run on the next frame on top of the elapsed ones, even while paused
+    pub pending_ticks: u32,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self {
+            accumulator: 0.0,
+            looping: false,
+            paused: false,
+            time_scale: 1.0,
+            pending_ticks: 0,
+        }
+    }
+}
+
+impl SimClock {
+    /// Fraction of a tick that has elapsed but not been simulated yet, for interpolating the
+    /// rendered positions between the last two ticks
+    pub fn overstep(&self, step: f32) -> f32 {
+        (self.accumulator / step as f64).clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Run criteria for the gameplay systems, running them once for every elapsed tick
+pub fn sim_tick(time: Res<Time>, tuning: Res<Tuning>, mut clock: ResMut<SimClock>) -> ShouldRun {
+    // first check of the frame
+    if !clock.looping {
+        clock.looping = true;
+        if !clock.paused {
+            clock.accumulator += time.delta_seconds_f64() * clock.time_scale as f64;
+        }
+    }
+
+    let step = tuning.time_step as f64;
+    if clock.pending_ticks > 0 {
+        clock.pending_ticks -= 1;
+        ShouldRun::YesAndCheckAgain
+    } else if !clock.paused && clock.accumulator >= step {
+        clock.accumulator -= step;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.looping = false;
+        ShouldRun::No
+    }
+}
@@ -0,0 +1,112 @@
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+/// Width of a single broad-phase chunk along the x axis
+const CHUNK_WIDTH: f32 = 4.0;
+
+/// Number of colliders tested against the player on the last tick
+pub const COLLISION_CANDIDATES: DiagnosticId =
+    DiagnosticId::from_u128(0x5d6c_9a3e_21f4_4b7a_8f0e_3c1d_7a92_b465);
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .init_resource::<CollisionGrid>()
+            .add_startup_system(collision_diagnostics_setup)
+            // PostUpdate sees the colliders spawned and despawned by this frame's commands
+            .add_system_to_stage(CoreStage::PostUpdate, collision_grid_update_system);
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct Collider;
+
+/// Sphere used for collision tests, centered `offset_y` above the entity's position
+#[derive(Component, Clone, Copy)]
+pub(crate) struct CollisionShape {
+    pub radius: f32,
+    pub offset_y: f32,
+}
+
+impl CollisionShape {
+    pub fn sphere(radius: f32) -> Self {
+        Self {
+            radius,
+            offset_y: 0.0,
+        }
+    }
+}
+
+/// Uniform grid of collider entities, keyed on the chunk of their x coordinate
+#[derive(Default)]
+pub(crate) struct CollisionGrid {
+    chunks: HashMap<i32, Vec<Entity>>,
+    entities: HashMap<Entity, i32>,
+}
+
+impl CollisionGrid {
+    fn chunk(x: f32) -> i32 {
+        (x / CHUNK_WIDTH).floor() as i32
+    }
+
+    /// Inserts the entity, or moves it if it has changed chunks
+    pub fn insert(&mut self, entity: Entity, x: f32) {
+        let chunk = Self::chunk(x);
+        match self.entities.insert(entity, chunk) {
+            Some(old) if old == chunk => return,
+            Some(old) => self.remove_from_chunk(entity, old),
+            None => {}
+        }
+        self.chunks.entry(chunk).or_default().push(entity);
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(chunk) = self.entities.remove(&entity) {
+            self.remove_from_chunk(entity, chunk);
+        }
+    }
+
+    fn remove_from_chunk(&mut self, entity: Entity, chunk: i32) {
+        if let Some(entities) = self.chunks.get_mut(&chunk) {
+            entities.retain(|&e| e != entity);
+            if entities.is_empty() {
+                self.chunks.remove(&chunk);
+            }
+        }
+    }
+
+    /// Entities in every chunk overlapping `x - reach..=x + reach`
+    pub fn nearby(&self, x: f32, reach: f32) -> impl Iterator<Item = Entity> + '_ {
+        (Self::chunk(x - reach)..=Self::chunk(x + reach))
+            .filter_map(|chunk| self.chunks.get(&chunk))
+            .flatten()
+            .copied()
+    }
+}
+
+fn collision_diagnostics_setup(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(
+        COLLISION_CANDIDATES,
+        "collision_candidates",
+        20,
+    ));
+}
+
+fn collision_grid_update_system(
+    mut grid: ResMut<CollisionGrid>,
+    changed_query: Query<
+        (Entity, &Transform),
+        (With<Collider>, Or<(Added<Collider>, Changed<Transform>)>),
+    >,
+    removed: RemovedComponents<Collider>,
+) {
+    for entity in removed.iter() {
+        grid.remove(entity);
+    }
+    for (entity, transform) in changed_query.iter() {
+        grid.insert(entity, transform.translation.x);
+    }
+}
@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+
+use crate::clock::SimClock;
+use crate::collision::CollisionShape;
+use crate::game::{game_setup, GameSystem, Obstacle, OnGameScreen, Recording, SimPosition, REM};
+use crate::physics::Player;
+use crate::replay::TickInput;
+
+use super::GameState;
+
+/// Range of the slow motion time scales, each key press halves or doubles it
+const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+const MAX_TIME_SCALE: f32 = 1.0;
+
+/// Keys of the debug controls, kept apart from the rebindable actions
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+const STEP_KEY: KeyCode = KeyCode::Period;
+const SLOWER_KEY: KeyCode = KeyCode::Minus;
+const FASTER_KEY: KeyCode = KeyCode::Equals;
+
+/// Frame advance, slow motion and a readout of the player's state, only in debug builds
+pub struct DebugPlugin;
+
+impl Plugin for DebugPlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .init_resource::<DebugOverlay>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Game).with_system(debug_setup.after(game_setup)),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playback).with_system(debug_setup.after(game_setup)),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Game)
+                    .with_system(debug_control_system.before(GameSystem::Simulation))
+                    .with_system(debug_text_system.after(GameSystem::Simulation)),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playback)
+                    .with_system(debug_control_system.before(GameSystem::Simulation))
+                    .with_system(debug_text_system.after(GameSystem::Simulation)),
+            );
+    }
+}
+
+/// Whether the debug readout is shown, kept from one run to the next
+#[derive(Default)]
+struct DebugOverlay {
+    visible: bool,
+}
+
+// A unit struct to help identify the debug UI component, since there may be many Text components
+#[derive(Component)]
+struct DebugText;
+
+fn debug_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // debug readout, below the fps counter
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(2.0 * REM),
+                    left: Val::Px(0.5 * REM),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/undefined-medium.ttf"),
+                    font_size: 0.75 * REM,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..default()
+        })
+        .insert(OnGameScreen)
+        .insert(DebugText);
+}
+
+// The step key pauses the simulation and runs a single tick per press, the pause key resumes it
+fn debug_control_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut overlay: ResMut<DebugOverlay>,
+    mut clock: ResMut<SimClock>,
+) {
+    if keyboard_input.just_pressed(TOGGLE_KEY) {
+        overlay.visible = !overlay.visible;
+    }
+    if keyboard_input.just_pressed(STEP_KEY) {
+        clock.paused = true;
+        clock.pending_ticks += 1;
+    }
+    if keyboard_input.just_pressed(SLOWER_KEY) {
+        clock.time_scale = (clock.time_scale / 2.0).max(MIN_TIME_SCALE);
+    }
+    if keyboard_input.just_pressed(FASTER_KEY) {
+        clock.time_scale = (clock.time_scale * 2.0).min(MAX_TIME_SCALE);
+    }
+}
+
+// Shows what the last tick did with the player, to tell a dropped input from a jump that
+// was not possible at that moment
+fn debug_text_system(
+    overlay: Res<DebugOverlay>,
+    clock: Res<SimClock>,
+    input: Res<TickInput>,
+    recording: Option<Res<Recording>>,
+    player_query: Query<(&Player, &SimPosition, &CollisionShape)>,
+    obstacle_query: Query<(&Transform, &CollisionShape), With<Obstacle>>,
+    mut query: Query<&mut Text, With<DebugText>>,
+) {
+    let mut text = match query.get_single_mut() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    if !overlay.visible {
+        text.sections[0].value.clear();
+        return;
+    }
+    // fallibility check needed as entities don't exist yet right after a restart
+    let (player, position, shape) = match player_query.get_single() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+
+    // gap between the surfaces of the player and of the closest obstacle
+    let center = position.current.truncate() + Vec2::new(0.0, shape.offset_y);
+    let nearest = obstacle_query
+        .iter()
+        .map(|(transform, obstacle)| {
+            transform.translation.truncate().distance(center) - obstacle.radius - shape.radius
+        })
+        .min_by(|a, b| a.total_cmp(b));
+
+    let pressed = |held: bool, name: &'static str| if held { name } else { "-" };
+    text.sections[0].value = format!(
+        "Tick {}  {}x{}\n\
+         Input: {} {} {}\n\
+         Velocity: x {:.2}  y {:.2}\n\
+         Jump state: {:?}\n\
+         Nearest obstacle: {}\n\
+         {:?}: step  {:?}/{:?}: slower/faster  {:?}: hide",
+        recording.map_or(0, |recording| recording.0.ticks()),
+        clock.time_scale,
+        if clock.paused { "  paused" } else { "" },
+        pressed(input.jump, "jump"),
+        pressed(input.boost, "boost"),
+        pressed(input.down, "down"),
+        player.velocity_x,
+        player.velocity_y,
+        player.jumping,
+        nearest.map_or("none".to_string(), |distance| format!("{:.2}", distance)),
+        STEP_KEY,
+        SLOWER_KEY,
+        FASTER_KEY,
+        TOGGLE_KEY,
+    );
+}
@@ -1,74 +1,204 @@
+use std::path::PathBuf;
+
 use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
-use bevy::{core::FixedTimestep, prelude::*};
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+
+use bevy::ecs::schedule::ShouldRun;
 
-use crate::level::Level;
+use crate::clock::{sim_tick, SimClock};
+use crate::collision::{Collider, CollisionGrid, CollisionShape, COLLISION_CANDIDATES};
+use crate::highscore::HighScores;
+use crate::input::Action;
+use crate::level::{Level, ObstacleKind, CEILING_HEIGHT, SPAWN_X};
 use crate::menu::MenuState;
+use crate::physics::{
+    camera_start, follow_player, move_player, resolve_contacts, state_hash, tick_invulnerability,
+    Bonus, BoostEnergy, Contact, Course, Health, Player, Runner, Score, PICKUP_RADIUS,
+};
+use crate::practice::Practice;
+use crate::replay::{Playback, Replay, TickInput};
+use crate::tuning::Tuning;
 
 use super::{despawn_screen, GameState, SeedState};
 
-/// Lockstep for the game engine
-const TIME_STEP: f32 = 1.0 / 60.0;
-
-/// Initial upwareds velocity for the jump
-const JUMP_INITIAL_VELOCITY: f32 = 5.0;
-/// Gravity constant for the jump
-const GRAVITY: f32 = 5.0;
+/// Seconds between visibility toggles while invulnerable
+const INVULNERABILITY_BLINK: f32 = 0.1;
 
-/// Default movement speed in the autoscroller
-const SCROLL_VELOCITY: f32 = 2.0;
-/// Boost velocity when the boost button is pressed
-const BOOST_VELOCITY: f32 = 5.0;
+/// Seconds a bonus popup stays on screen, rising and fading out
+const POPUP_SECONDS: f32 = 1.0;
 
-/// Radius of the spheres, both for player and obstacles
-const SPHERE_RADIUS: f32 = 0.5;
+/// Size of the boost energy bar in the HUD
+const ENERGY_BAR_WIDTH: f32 = 8.0 * REM;
+const ENERGY_BAR_HEIGHT: f32 = 0.5 * REM;
 
 /// Fake unit for font-related calculations for visual consistency
-const REM: f32 = 24.0;
+pub(crate) const REM: f32 = 24.0;
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app // load-bearing comment, better readability for chains below
+            .init_resource::<SimClock>()
+            .init_resource::<GravityZones>()
+            .init_resource::<TickInput>()
+            .init_resource::<SavedReplay>()
+            .init_resource::<StateHashing>()
             .add_system_set(SystemSet::on_enter(GameState::Game).with_system(game_setup))
+            .add_system_set(SystemSet::on_enter(GameState::Playback).with_system(game_setup))
+            .add_system_set(SystemSet::on_update(GameState::Game).with_system(pause_system))
+            .add_system_set(SystemSet::on_update(GameState::Playback).with_system(pause_system))
             .add_system_set(
-                SystemSet::on_update(GameState::Game)
-                    .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
-                    .with_system(player_movement_system)
-                    .with_system(camera_movement_system)
-                    .with_system(check_for_collisions)
+                SystemSet::new()
+                    .with_run_criteria(simulation_run_criteria.label(SimulationTick))
+                    .label(GameSystem::Simulation)
+                    .with_system(tick_input_system.label(GameSystem::Input))
+                    .with_system(
+                        record_input_system
+                            .after(GameSystem::Input)
+                            .before(GameSystem::StorePositions),
+                    )
+                    .with_system(
+                        store_previous_positions
+                            .label(GameSystem::StorePositions)
+                            .after(GameSystem::Input),
+                    )
+                    .with_system(
+                        player_movement_system
+                            .label(GameSystem::Movement)
+                            .after(GameSystem::StorePositions),
+                    )
+                    // the camera follows the player once the collisions have moved it for good
+                    .with_system(
+                        camera_movement_system
+                            .label(GameSystem::Camera)
+                            .after(check_for_collisions),
+                    )
+                    .with_system(
+                        state_hash_system
+                            .label(GameSystem::StateHash)
+                            .after(GameSystem::Camera)
+                            .after(record_input_system),
+                    )
+                    .with_system(
+                        score_system
+                            .label(GameSystem::Score)
+                            .after(check_for_collisions),
+                    )
+                    .with_system(
+                        ghost_movement_system
+                            .after(GameSystem::StorePositions)
+                            .after(GameSystem::Input),
+                    )
+                    .with_system(ghost_delta_text_update_system.after(ghost_movement_system))
+                    .with_system(check_for_collisions.after(GameSystem::Movement))
+                    .with_system(invulnerability_system.before(check_for_collisions))
                     .with_system(fps_text_update_system)
-                    .with_system(score_text_update_system),
+                    .with_system(score_text_update_system.after(score_system))
+                    .with_system(lives_text_update_system)
+                    .with_system(energy_bar_update_system),
+            )
+            // rendering runs every frame after all of the frame's simulation ticks
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolation_system
+                    .label(GameSystem::Interpolation)
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                crouch_visual_system
+                    .after(GameSystem::Interpolation)
+                    .before(TransformSystem::TransformPropagate),
             )
             .add_event::<CollisionEvent>()
+            .add_event::<NearMissEvent>()
+            .add_event::<JumpEvent>()
+            .add_event::<BoostEvent>()
+            // popups animate in real time, also while paused
+            .add_system(popup_system)
+            .add_system_set(
+                SystemSet::on_enter(GameState::GameOverMenu).with_system(save_replay_system),
+            )
             .add_system_set(
                 SystemSet::on_exit(GameState::GameOverMenu)
                     .with_system(despawn_screen::<OnGameScreen>),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Playback).with_system(despawn_screen::<OnGameScreen>),
             );
     }
 }
 
+// Runs the gameplay systems once per elapsed tick, during runs and playbacks only
+fn simulation_run_criteria(
+    game_state: Res<State<GameState>>,
+    time: Res<Time>,
+    tuning: Res<Tuning>,
+    clock: ResMut<SimClock>,
+) -> ShouldRun {
+    match game_state.current() {
+        GameState::Game | GameState::Playback => sim_tick(time, tuning, clock),
+        GameState::MainMenu | GameState::GameOverMenu => ShouldRun::No,
+    }
+}
+
+/// Run criteria of the gameplay systems, shared with the systems of other plugins that run on
+/// every tick
+#[derive(RunCriteriaLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SimulationTick;
+
+// Labels for ordering the gameplay systems within a fixed timestep
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum GameSystem {
+    Simulation,
+    Input,
+    StorePositions,
+    Movement,
+    Camera,
+    Score,
+    StateHash,
+    Interpolation,
+}
+
 // Tag component used to tag entities added on the game screen
 #[derive(Component)]
-struct OnGameScreen;
+pub(crate) struct OnGameScreen;
 
 /// set up a simple 3D scene
-fn game_setup(
+pub(crate) fn game_setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
     seed_state: ResMut<State<SeedState>>,
+    tuning: Res<Tuning>,
+    mut clock: ResMut<SimClock>,
+    mut saved_replay: ResMut<SavedReplay>,
+    playback: Option<Res<Playback>>,
+    practice: Option<Res<Practice>>,
+    high_scores: Res<HighScores>,
 ) {
+    // a playback keeps its pause and speed when restarted to seek backwards
+    if playback.is_none() {
+        clock.paused = false;
+        clock.time_scale = 1.0;
+    }
     let seed = seed_state.current().value;
-    let level = Level::new(seed);
+    let level = Level::new(seed, &tuning.speed_curve);
+    commands.insert_resource(GravityZones(level.gravity_zones.clone()));
+    commands.insert_resource(Recording(Replay::new(seed, tuning.clone())));
+    commands.insert_resource(Score::default());
+    saved_replay.0 = None;
+    let course = Course::new(&level, &tuning);
 
     // spheres to jump over
     for obstacle in level.obstacles {
         commands
             .spawn_bundle(PbrBundle {
                 mesh: meshes.add(Mesh::from(shape::Icosphere {
-                    radius: SPHERE_RADIUS,
+                    radius: tuning.sphere_radius,
                     subdivisions: 32,
                 })),
                 material: materials.add(obstacle.material),
@@ -76,8 +206,33 @@ fn game_setup(
                 ..Default::default()
             })
             .insert(OnGameScreen)
-            .insert(Obstacle)
-            .insert(Collider);
+            .insert(Obstacle {
+                kind: obstacle.kind,
+            })
+            .insert(Collider)
+            .insert(CollisionShape::sphere(tuning.sphere_radius));
+    }
+
+    // boost energy pickups
+    for (x, y) in level.pickups {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Icosphere {
+                    radius: PICKUP_RADIUS,
+                    subdivisions: 8,
+                })),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::YELLOW,
+                    emissive: Color::YELLOW,
+                    ..Default::default()
+                }),
+                transform: Transform::from_xyz(x, y, 0.0),
+                ..Default::default()
+            })
+            .insert(OnGameScreen)
+            .insert(Pickup)
+            .insert(Collider)
+            .insert(CollisionShape::sphere(PICKUP_RADIUS));
     }
 
     // lights
@@ -148,11 +303,35 @@ fn game_setup(
         })
         .insert(OnGameScreen);
 
+    // ceilings over the gravity zones
+    for (start, end) in level.gravity_zones {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box {
+                    min_x: start,
+                    max_x: end,
+                    min_y: CEILING_HEIGHT + 0.5,
+                    max_y: CEILING_HEIGHT + 10.0,
+                    min_z: -5.0,
+                    max_z: 5.0,
+                })),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::hex("272822").unwrap(),
+                    metallic: 0.5,
+                    perceptual_roughness: 0.5,
+                    ..Default::default()
+                }),
+                transform: Transform::from_xyz(0.0, 0.0, -5.0),
+                ..Default::default()
+            })
+            .insert(OnGameScreen);
+    }
+
     // player
-    commands
+    let player = commands
         .spawn_bundle(PbrBundle {
             mesh: meshes.add(Mesh::from(shape::Icosphere {
-                radius: SPHERE_RADIUS,
+                radius: tuning.sphere_radius,
                 subdivisions: 32,
             })),
             material: materials.add(StandardMaterial {
@@ -162,21 +341,88 @@ fn game_setup(
                 reflectance: 1.0,
                 ..Default::default()
             }),
-            transform: Transform::from_xyz(-5.0, 0.0, 0.0),
+            transform: Transform::from_xyz(SPAWN_X, 0.0, 0.0),
             ..Default::default()
         })
         .insert(OnGameScreen)
-        .insert(Player::default());
+        .insert(SimPosition::new(Vec3::new(SPAWN_X, 0.0, 0.0)))
+        .insert(Player::default())
+        .insert(CollisionShape::sphere(tuning.sphere_radius))
+        .insert(Health::new(tuning.lives))
+        .insert(BoostEnergy {
+            current: tuning.boost_energy.max,
+        })
+        .id();
+
+    // ghost of the personal best on this level, raced against outside of playbacks and practice
+    let personal_best = match (playback, practice) {
+        (None, None) => high_scores.personal_best(seed, &tuning),
+        _ => None,
+    };
+    if let Some(replay) = personal_best {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Icosphere {
+                    radius: tuning.sphere_radius,
+                    subdivisions: 32,
+                })),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgba(1.0, 1.0, 1.0, 0.3),
+                    alpha_mode: AlphaMode::Blend,
+                    ..Default::default()
+                }),
+                transform: Transform::from_xyz(SPAWN_X, 0.0, 0.0),
+                ..Default::default()
+            })
+            .insert(OnGameScreen)
+            .insert(SimPosition::new(Vec3::new(SPAWN_X, 0.0, 0.0)))
+            .insert(CollisionShape::sphere(tuning.sphere_radius))
+            .insert(Ghost {
+                runner: Runner::new(&course, &tuning),
+                course,
+                inputs: replay.inputs(),
+                tick: 0,
+            });
+
+        // distance to the ghost, between the energy bar and the bonus popups
+        commands
+            .spawn_bundle(TextBundle {
+                style: Style {
+                    align_self: AlignSelf::FlexEnd,
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        top: Val::Px(4.0 * REM),
+                        right: Val::Px(0.5 * REM),
+                        ..default()
+                    },
+                    ..default()
+                },
+                text: Text::with_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/undefined-medium.ttf"),
+                        font_size: REM,
+                        color: Color::WHITE,
+                    },
+                    Default::default(),
+                ),
+                ..default()
+            })
+            .insert(OnGameScreen)
+            .insert(GhostDeltaText);
+    }
 
     // camera
+    let camera = camera_start(&tuning);
     commands
         .spawn_bundle(PerspectiveCameraBundle {
-            transform: Transform::from_xyz(0.0, 0.0, 8.0)
-                .looking_at(Vec3::new(0.0, 2.5, 0.0), Vec3::Y),
+            transform: Transform::from_translation(camera)
+                .looking_at(Vec3::new(camera.x, 2.5, 0.0), Vec3::Y),
             ..default()
         })
         .insert(OnGameScreen)
-        .insert(Camera::default());
+        .insert(SimPosition::new(camera))
+        .insert(CameraRig { target: player });
 
     // fps counter
     commands
@@ -256,6 +502,102 @@ fn game_setup(
         .insert(OnGameScreen)
         .insert(ScoreText);
 
+    // lives counter
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(2.0 * REM),
+                    right: Val::Px(0.5 * REM),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: "Lives: ".to_string(),
+                        style: TextStyle {
+                            font: asset_server.load("fonts/undefined-medium.ttf"),
+                            font_size: REM,
+                            color: Color::WHITE,
+                        },
+                    },
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle {
+                            font: asset_server.load("fonts/undefined-medium.ttf"),
+                            font_size: REM,
+                            color: Color::WHITE,
+                        },
+                    },
+                ],
+                ..default()
+            },
+            ..default()
+        })
+        .insert(OnGameScreen)
+        .insert(LivesText);
+
+    // pause indicator, empty while not paused
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::Center,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(0.5 * REM),
+                    left: Val::Percent(45.0),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/undefined-medium.ttf"),
+                    font_size: 2.0 * REM,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..default()
+        })
+        .insert(OnGameScreen)
+        .insert(PausedText);
+
+    // boost energy bar
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(3.5 * REM),
+                    right: Val::Px(0.5 * REM),
+                    ..default()
+                },
+                size: Size::new(Val::Px(ENERGY_BAR_WIDTH), Val::Px(ENERGY_BAR_HEIGHT)),
+                ..default()
+            },
+            color: Color::rgb(0.15, 0.15, 0.15).into(),
+            ..default()
+        })
+        .insert(OnGameScreen)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        ..default()
+                    },
+                    color: Color::YELLOW.into(),
+                    ..default()
+                })
+                .insert(EnergyBar);
+        });
+
     // seed
     commands
         .spawn_bundle(TextBundle {
@@ -285,33 +627,42 @@ fn game_setup(
         .insert(OnGameScreen);
 }
 
+/// Stretches of the current level where gravity is flipped, see `Level::gravity_zones`
+#[derive(Default)]
+struct GravityZones(Vec<(f32, f32)>);
+
 #[derive(Component)]
-struct Player {
-    jumping: JumpState,
-    collided: bool,
-    velocity_x: f32,
-    velocity_y: f32,
+pub(crate) struct Obstacle {
+    pub kind: ObstacleKind,
 }
 
-impl Default for Player {
-    fn default() -> Self {
+#[derive(Component)]
+pub(crate) struct Pickup;
+
+/// Position of a moving entity in the simulation, kept apart from its `Transform`.
+/// The simulation only advances in fixed ticks, so the rendered position is
+/// interpolated between the last two ticks to avoid stutter.
+#[derive(Component)]
+pub(crate) struct SimPosition {
+    previous: Vec3,
+    pub current: Vec3,
+}
+
+impl SimPosition {
+    fn new(position: Vec3) -> Self {
         Self {
-            jumping: JumpState::OnFloor,
-            velocity_x: SCROLL_VELOCITY,
-            velocity_y: 0.0,
-            collided: false,
+            previous: position,
+            current: position,
         }
     }
-}
 
-enum JumpState {
-    OnFloor,
-    InAir,
+    /// Moves the entity without interpolating from where it was
+    pub(crate) fn teleport(&mut self, position: Vec3) {
+        self.previous = position;
+        self.current = position;
+    }
 }
 
-#[derive(Component)]
-struct Obstacle;
-
 // A unit struct to help identify the FPS UI component, since there may be many Text components
 #[derive(Component)]
 struct FpsText;
@@ -320,101 +671,342 @@ struct FpsText;
 #[derive(Component)]
 struct ScoreText;
 
+// A unit struct to help identify the Lives UI component, since there may be many Text components
+#[derive(Component)]
+struct LivesText;
+
+// A unit struct to help identify the pause UI component, since there may be many Text components
+#[derive(Component)]
+struct PausedText;
+
+// A unit struct to help identify the filled part of the boost energy bar
 #[derive(Component)]
-struct Collider;
+struct EnergyBar;
 
+/// The player was hit by an obstacle, losing a life
+pub(crate) struct CollisionEvent {
+    pub kind: ObstacleKind,
+    /// Whether the hit cost the last life, ending the run
+    pub fatal: bool,
+}
+
+/// The player took off from the floor
 #[derive(Default)]
-struct CollisionEvent;
+pub(crate) struct JumpEvent;
 
-fn player_movement_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Player, &mut Transform)>,
+/// The player started boosting
+#[derive(Default)]
+pub(crate) struct BoostEvent;
+
+/// The player passed an obstacle without touching it, within `NEAR_MISS_MARGIN`
+#[derive(Default)]
+struct NearMissEvent;
+
+/// Text of the bonus points just earned
+#[derive(Component)]
+struct Popup {
+    /// Seconds on screen
+    age: f32,
+    /// Position from the top of the screen it rises from
+    top: f32,
+}
+
+/// Replay of the run in progress
+pub(crate) struct Recording(pub Replay);
+
+/// Whether the state hash of every tick is stored in the replays and checked during playbacks,
+/// see `physics::state_hash`. On unless the game is started with `--no-state-hashes`.
+pub(crate) struct StateHashing(pub bool);
+
+impl Default for StateHashing {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Where the replay of the last run was saved, if it was
+#[derive(Default)]
+pub(crate) struct SavedReplay(pub Option<PathBuf>);
+
+/// A ghost replaying the inputs of the personal best run, simulated next to the player.
+/// It has neither `Player` nor `Collider`, so that `check_for_collisions` never sees it.
+#[derive(Component)]
+struct Ghost {
+    runner: Runner,
+    course: Course,
+    inputs: Vec<TickInput>,
+    tick: usize,
+}
+
+// A unit struct to help identify the ghost distance UI component, since there may be many Text components
+#[derive(Component)]
+struct GhostDeltaText;
+
+fn store_previous_positions(mut query: Query<&mut SimPosition>) {
+    for mut position in query.iter_mut() {
+        position.previous = position.current;
+    }
+}
+
+fn interpolation_system(
+    clock: Res<SimClock>,
+    tuning: Res<Tuning>,
+    mut query: Query<(&SimPosition, &mut Transform)>,
 ) {
-    // fallibility check needed as entities don't exist yet in menus
-    let (mut player, mut transform) = match query.get_single_mut() {
-        Ok(val) => val,
-        Err(_) => return,
+    let alpha = clock.overstep(tuning.time_step);
+    for (position, mut transform) in query.iter_mut() {
+        transform.translation = position.previous.lerp(position.current, alpha);
+    }
+}
+
+// Squashes the player to match its collision shape while ducking
+fn crouch_visual_system(
+    tuning: Res<Tuning>,
+    mut query: Query<(&CollisionShape, &mut Transform), Or<(With<Player>, With<Ghost>)>>,
+) {
+    for (shape, mut transform) in query.iter_mut() {
+        transform.translation.y += shape.offset_y;
+        transform.scale = Vec3::new(1.0, shape.radius / tuning.sphere_radius, 1.0);
+    }
+}
+
+fn pause_system(
+    actions: Res<Input<Action>>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut clock: ResMut<SimClock>,
+    mut query: Query<&mut Text, With<PausedText>>,
+) {
+    // pause when a gamepad is unplugged, the player may have lost their controller mid-run
+    let unplugged = gamepad_events
+        .iter()
+        .any(|GamepadEvent(_, event_type)| *event_type == GamepadEventType::Disconnected);
+    if unplugged && !clock.paused {
+        clock.paused = true;
+    } else if actions.just_pressed(Action::Pause) {
+        clock.paused = !clock.paused;
+    } else {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = if clock.paused { "Paused" } else { "" }.to_string();
+    }
+}
+
+// Samples the inputs for this tick, all of the tick's systems read them from `TickInput`.
+// During a playback they come from the replay instead.
+fn tick_input_system(
+    actions: Res<Input<Action>>,
+    playback: Option<Res<Playback>>,
+    recording: Option<Res<Recording>>,
+    mut input: ResMut<TickInput>,
+) {
+    *input = match (playback, recording) {
+        (Some(playback), Some(recording)) => playback.input(recording.0.ticks()),
+        _ => TickInput {
+            jump: actions.pressed(Action::Jump),
+            boost: actions.pressed(Action::Boost),
+            down: actions.pressed(Action::Down),
+        },
     };
+}
 
+fn record_input_system(
+    input: Res<TickInput>,
+    recording: Option<ResMut<Recording>>,
+    query: Query<&Player>,
+) {
+    // fallibility check needed as entities don't exist yet in menus
+    let (mut recording, player) = match (recording, query.get_single()) {
+        (Some(recording), Ok(player)) => (recording, player),
+        _ => return,
+    };
+    // the run is over, the ticks simulated after the last hit in the same frame are not part of it
     if player.collided {
         return;
     }
+    recording.0.push(*input);
+}
 
-    // x direction
-    if keyboard_input.pressed(KeyCode::Right) {
-        player.velocity_x = BOOST_VELOCITY;
-    } else {
-        player.velocity_x = SCROLL_VELOCITY;
+// Hashes the state at the end of every recorded tick, and compares it with the recorded hashes
+// during a playback
+fn state_hash_system(
+    state_hashing: Res<StateHashing>,
+    recording: Option<ResMut<Recording>>,
+    playback: Option<ResMut<Playback>>,
+    player_query: Query<(&Player, &SimPosition)>,
+    camera_query: Query<&SimPosition, With<CameraRig>>,
+) {
+    if !state_hashing.0 {
+        return;
     }
+    // fallibility check needed as entities don't exist yet in menus
+    let (mut recording, (player, position), camera) = match (
+        recording,
+        player_query.get_single(),
+        camera_query.get_single(),
+    ) {
+        (Some(recording), Ok(player), Ok(camera)) => (recording, player, camera),
+        _ => return,
+    };
+    let replay = &mut recording.0;
+    // the ticks simulated after the end of the run were not recorded
+    let tick = replay.hash_count();
+    if tick >= replay.ticks() {
+        return;
+    }
+    let hash = state_hash(player, position.current, camera.current);
+    replay.push_hash(hash);
 
-    let translation = &mut transform.translation;
-    translation.x += player.velocity_x * TIME_STEP;
-
-    // y direction
-    if keyboard_input.pressed(KeyCode::Space) {
-        match &player.jumping {
-            JumpState::OnFloor => {
-                player.jumping = JumpState::InAir;
-                player.velocity_y = JUMP_INITIAL_VELOCITY;
+    if let Some(mut playback) = playback {
+        if playback.outcome.is_none() {
+            if let Some(desync) = playback.replay.check_hash(tick, hash) {
+                warn!("{}", desync);
+                playback.outcome = Some(desync);
             }
-            JumpState::InAir => {}
         }
     }
+}
 
-    // floor min height
-    if translation.y < 0.0 {
-        player.jumping = JumpState::OnFloor;
-        player.velocity_y = 0.0;
-        translation.y = 0.0;
+pub(crate) fn save_replay_system(
+    tuning: Res<Tuning>,
+    recording: Option<ResMut<Recording>>,
+    mut saved_replay: ResMut<SavedReplay>,
+    score: Option<Res<Score>>,
+    query: Query<&SimPosition, With<Player>>,
+) {
+    let (mut recording, score, position) = match (recording, score, query.get_single()) {
+        (Some(recording), Some(score), Ok(position)) => (recording, score, position),
+        _ => return,
+    };
+    let replay = &mut recording.0;
+    // a hot reload during the run would not be simulated again the same way
+    if replay.tuning != *tuning {
+        warn!("Not saving the replay, the tuning changed during the run");
+        return;
     }
-
-    player.velocity_y -= GRAVITY * TIME_STEP;
-    let velocity = player.velocity_y;
-
-    // dbg!(&translation.y);
-    match player.jumping {
-        JumpState::OnFloor => translation.y = 0.0,
-        JumpState::InAir => translation.y += velocity * TIME_STEP,
+    replay.distance = position.current.x - SPAWN_X;
+    replay.score = Some(score.total());
+    match replay.save() {
+        Ok(path) => {
+            info!("Saved replay of {} ticks to {:?}", replay.ticks(), path);
+            saved_replay.0 = Some(path);
+        }
+        Err(error) => warn!("Could not save the replay: {}", error),
     }
 }
 
-// TODO: remove duplicate code...
-#[derive(Component)]
-struct Camera {
-    velocity_x: f32,
-    stopped: bool,
+fn ghost_movement_system(
+    tuning: Res<Tuning>,
+    mut query: Query<(&mut Ghost, &mut SimPosition, &mut CollisionShape)>,
+) {
+    for (mut ghost, mut position, mut shape) in query.iter_mut() {
+        let ghost = &mut *ghost;
+        // past the end of the recorded run, the ghost stays where its run ended
+        let input = match ghost.inputs.get(ghost.tick) {
+            Some(&val) => val,
+            None => continue,
+        };
+        ghost.tick += 1;
+        ghost.runner.step(input, &ghost.course, &tuning);
+        position.current = ghost.runner.position;
+        *shape = ghost.runner.shape;
+    }
 }
 
-impl Camera {
-    fn default() -> Self {
-        Camera {
-            velocity_x: SCROLL_VELOCITY,
-            stopped: false,
-        }
+fn ghost_delta_text_update_system(
+    mut text_query: Query<&mut Text, With<GhostDeltaText>>,
+    player_query: Query<&SimPosition, With<Player>>,
+    ghost_query: Query<&SimPosition, With<Ghost>>,
+) {
+    // fallibility check needed as entities don't exist yet in menus
+    let (player, ghost) = match (player_query.get_single(), ghost_query.get_single()) {
+        (Ok(player), Ok(ghost)) => (player, ghost),
+        _ => return,
+    };
+    let delta = player.current.x - ghost.current.x;
+    for mut text in text_query.iter_mut() {
+        let section = &mut text.sections[0];
+        section.value = format!("Ghost: {:+.2}", delta);
+        section.style.color = if delta >= 0.0 {
+            Color::GREEN
+        } else {
+            Color::RED
+        };
     }
 }
 
-fn camera_movement_system(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Camera, &mut Transform)>,
+fn player_movement_system(
+    input: Res<TickInput>,
+    tuning: Res<Tuning>,
+    gravity_zones: Res<GravityZones>,
+    mut jump_events: EventWriter<JumpEvent>,
+    mut boost_events: EventWriter<BoostEvent>,
+    mut query: Query<(
+        &mut Player,
+        &mut BoostEnergy,
+        &mut CollisionShape,
+        &mut SimPosition,
+    )>,
 ) {
     // fallibility check needed as entities don't exist yet in menus
-    let (mut camera, mut transform) = match query.get_single_mut() {
+    let (mut player, mut energy, mut shape, mut position) = match query.get_single_mut() {
         Ok(val) => val,
         Err(_) => return,
     };
-    if camera.stopped {
-        return;
+    let was_boosting = player.boosting;
+    move_player(
+        &mut player,
+        &mut energy,
+        &mut shape,
+        &mut position.current,
+        *input,
+        &tuning,
+        &gravity_zones.0,
+    );
+    if player.jumped {
+        jump_events.send_default();
     }
-
-    if keyboard_input.pressed(KeyCode::Right) {
-        camera.velocity_x = BOOST_VELOCITY;
-    } else {
-        camera.velocity_x = SCROLL_VELOCITY;
+    if player.boosting && !was_boosting {
+        boost_events.send_default();
     }
+}
 
-    let translation = &mut transform.translation;
-    translation.x += camera.velocity_x * TIME_STEP;
+/// Camera that follows the target entity, as configured in `Tuning::camera`
+#[derive(Component)]
+pub(crate) struct CameraRig {
+    target: Entity,
+}
+
+fn camera_movement_system(
+    tuning: Res<Tuning>,
+    mut query: Query<(&CameraRig, &mut SimPosition), Without<Player>>,
+    target_query: Query<(&Player, &SimPosition), Without<CameraRig>>,
+) {
+    // fallibility check needed as entities don't exist yet in menus
+    let (rig, mut position) = match query.get_single_mut() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    let (player, target) = match target_query.get(rig.target) {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    position.current = follow_player(position.current, player, target.current, &tuning);
+}
+
+fn invulnerability_system(
+    tuning: Res<Tuning>,
+    mut query: Query<(&mut Health, &mut Visibility), With<Player>>,
+) {
+    // fallibility check needed as entities don't exist yet in menus
+    let (mut health, mut visibility) = match query.get_single_mut() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    tick_invulnerability(&mut health, &tuning);
+    // blink the player while invulnerable
+    visibility.is_visible = health.invulnerable_for <= 0.0
+        || ((health.invulnerable_for / INVULNERABILITY_BLINK) as u32).is_multiple_of(2);
 }
 
 fn fps_text_update_system(
@@ -434,27 +1026,163 @@ fn fps_text_update_system(
     }
 }
 
+// Earns the points for the tick, once the collisions have settled where the player is,
+// and pops up the bonuses
+fn score_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut near_miss_events: EventReader<NearMissEvent>,
+    clock: Res<SimClock>,
+    playback: Option<Res<Playback>>,
+    score: Option<ResMut<Score>>,
+    query: Query<(&Player, &SimPosition)>,
+) {
+    let hit = collision_events.iter().count() > 0;
+    let near_misses = near_miss_events.iter().count() as u32;
+    // fallibility check needed as entities don't exist yet in menus
+    let (mut score, (player, position)) = match (score, query.get_single()) {
+        (Some(score), Ok(val)) => (score, val),
+        _ => return,
+    };
+    let bonuses = score.tick(player, position.current, hit, near_misses);
+    // the ticks skipped over by a seek are not shown, so neither are their bonuses
+    let seeking = playback.is_some_and(|playback| playback.seek_to.is_some());
+    if seeking || clock.pending_ticks > 0 {
+        return;
+    }
+
+    for (i, bonus) in bonuses.into_iter().enumerate() {
+        let text = match bonus {
+            Bonus::NearMiss => format!("+{} Near miss", bonus.points()),
+            Bonus::CleanJump { combo } => format!("+{} Clean jump x{}", bonus.points(), combo),
+        };
+        let top = (5.0 + i as f32) * REM;
+        commands
+            .spawn_bundle(TextBundle {
+                style: Style {
+                    align_self: AlignSelf::Center,
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        top: Val::Px(top),
+                        left: Val::Percent(45.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                text: Text::with_section(
+                    text,
+                    TextStyle {
+                        font: asset_server.load("fonts/undefined-medium.ttf"),
+                        font_size: REM,
+                        color: Color::YELLOW,
+                    },
+                    Default::default(),
+                ),
+                ..default()
+            })
+            .insert(OnGameScreen)
+            .insert(Popup { age: 0.0, top });
+    }
+}
+
+fn popup_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Popup, &mut Style, &mut Text)>,
+) {
+    for (entity, mut popup, mut style, mut text) in query.iter_mut() {
+        popup.age += time.delta_seconds();
+        if popup.age >= POPUP_SECONDS {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        let progress = popup.age / POPUP_SECONDS;
+        style.position.top = Val::Px(popup.top - progress * REM);
+        text.sections[0].style.color.set_a(1.0 - progress);
+    }
+}
+
 fn score_text_update_system(
+    score: Option<Res<Score>>,
     mut query: Query<&mut Text, With<ScoreText>>,
-    mut playertransform_query: Query<(&Player, &Transform)>,
+    player_query: Query<&Player>,
 ) {
     // fallibility check needed as entities don't exist yet in menus
-    let (_player, transform) = match playertransform_query.get_single_mut() {
+    let (score, player) = match (score, player_query.get_single()) {
+        (Some(score), Ok(player)) => (score, player),
+        _ => return,
+    };
+    let mut scoretext = match query.get_single_mut() {
         Ok(val) => val,
         Err(_) => return,
     };
-    let mut scoretext = match query.get_single_mut() {
+    let multiplier = score.multiplier(player);
+    scoretext.sections[1].value = if multiplier > 1.0 {
+        format!("{} x{}", score.total(), multiplier)
+    } else {
+        score.total().to_string()
+    };
+}
+
+fn lives_text_update_system(
+    mut query: Query<&mut Text, With<LivesText>>,
+    health_query: Query<&Health, With<Player>>,
+) {
+    // fallibility check needed as entities don't exist yet in menus
+    let health = match health_query.get_single() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    let mut livestext = match query.get_single_mut() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    livestext.sections[1].value = format!("{}", health.lives);
+}
+
+fn energy_bar_update_system(
+    tuning: Res<Tuning>,
+    energy_query: Query<&BoostEnergy, With<Player>>,
+    mut query: Query<&mut Style, With<EnergyBar>>,
+) {
+    // fallibility check needed as entities don't exist yet in menus
+    let energy = match energy_query.get_single() {
         Ok(val) => val,
         Err(_) => return,
     };
-    scoretext.sections[1].value = format!("{:.2}", transform.translation.x);
+    let mut style = match query.get_single_mut() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+    let fraction = (energy.current / tuning.boost_energy.max).clamp(0.0, 1.0);
+    style.size.width = Val::Percent(100.0 * fraction);
 }
 
 fn check_for_collisions(
-    mut player_query: Query<(&mut Player, &Transform)>,
-    collider_query: Query<(Entity, &Transform, Option<&Obstacle>), With<Collider>>,
+    mut player_query: Query<(
+        &mut Player,
+        &mut Health,
+        &mut BoostEnergy,
+        &CollisionShape,
+        &SimPosition,
+    )>,
+    mut collider_query: Query<
+        (
+            &Transform,
+            &CollisionShape,
+            &mut Visibility,
+            Option<&Pickup>,
+            Option<&Obstacle>,
+        ),
+        With<Collider>,
+    >,
+    grid: Res<CollisionGrid>,
+    tuning: Res<Tuning>,
+    practice: Option<Res<Practice>>,
+    mut diagnostics: ResMut<Diagnostics>,
     mut collision_events: EventWriter<CollisionEvent>,
-    mut camera_query: Query<&mut Camera>,
+    mut near_miss_events: EventWriter<NearMissEvent>,
     mut menu_state: ResMut<State<MenuState>>,
     mut game_state: ResMut<State<GameState>>,
 ) {
@@ -466,27 +1194,68 @@ fn check_for_collisions(
     }
 
     // fallibility check needed as entities don't exist yet in menus
-    let (mut player, player_trans) = match player_query.get_single_mut() {
-        Ok(val) => val,
-        Err(_) => return,
-    };
-    let mut camera = match camera_query.get_single_mut() {
-        Ok(val) => val,
-        Err(_) => return,
-    };
-    let (x1, y1) = (player_trans.translation.x, player_trans.translation.y);
-    // Simple sphere collision based on center and radius
-    for (_sphere_ent, sphere_trans, _sphere_obs) in collider_query.iter() {
-        let (x2, y2) = (sphere_trans.translation.x, sphere_trans.translation.y);
-        let distance = ((x2 - x1).powf(2.0) + (y2 - y1).powf(2.0)).sqrt();
-        if distance <= SPHERE_RADIUS * 2.0 {
-            collision_events.send_default();
-            player.collided = true;
-            camera.stopped = true;
-
-            game_state.set(GameState::GameOverMenu).unwrap();
-            menu_state.set(MenuState::GameOver).unwrap();
-            break;
+    let (mut player, mut health, mut energy, player_shape, player_position) =
+        match player_query.get_single_mut() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+    // the game over is already on its way, from an earlier tick of this frame
+    if player.collided {
+        return;
+    }
+    // Broad phase: only the colliders in the chunks around the player can touch it
+    let x = player_position.current.x;
+    let contacts: Vec<_> = grid
+        .nearby(x, player_shape.radius + tuning.sphere_radius)
+        .filter_map(|entity| {
+            let (transform, shape, visibility, pickup, _) = collider_query.get(entity).ok()?;
+            // a hidden pickup was collected already
+            if pickup.is_some() && !visibility.is_visible {
+                return None;
+            }
+            Some(Contact {
+                key: entity,
+                x: transform.translation.x,
+                y: transform.translation.y,
+                radius: shape.radius,
+                pickup: pickup.is_some(),
+            })
+        })
+        .collect();
+    diagnostics.add_measurement(COLLISION_CANDIDATES, contacts.len() as f64);
+
+    let outcome = resolve_contacts(
+        &mut player,
+        &mut health,
+        &mut energy,
+        player_shape,
+        player_position.current,
+        contacts,
+        &tuning,
+    );
+    // collected pickups are only hidden, right away so that the next tick of this frame skips
+    // them, and a practice savestate may bring them back
+    for entity in outcome.picked_up {
+        if let Ok((_, _, mut visibility, ..)) = collider_query.get_mut(entity) {
+            visibility.is_visible = false;
         }
     }
+    let hit_by = outcome
+        .hit
+        .and_then(|entity| collider_query.get(entity).ok())
+        .and_then(|(.., obstacle)| obstacle);
+    if let Some(obstacle) = hit_by {
+        collision_events.send(CollisionEvent {
+            kind: obstacle.kind,
+            fatal: player.collided,
+        });
+    }
+    for _ in 0..outcome.near_misses {
+        near_miss_events.send_default();
+    }
+    // a playback stays on screen, to be seeked back, and practice goes back to a savestate
+    if player.collided && *game_state.current() == GameState::Game && practice.is_none() {
+        game_state.set(GameState::GameOverMenu).unwrap();
+        menu_state.set(MenuState::GameOver).unwrap();
+    }
 }
@@ -0,0 +1,328 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{save_replay_system, SavedReplay};
+use crate::physics::Score;
+use crate::replay::Replay;
+use crate::storage;
+use crate::tuning::Tuning;
+
+use super::{GameState, SeedState};
+
+/// Number of runs kept in each table
+pub const TABLE_SIZE: usize = 10;
+
+/// File the high scores are saved to, in the data directory
+const HIGH_SCORES_FILE: &str = "highscores.ron";
+
+/// Longest name that can be entered for a high score
+pub const NAME_LENGTH: usize = 12;
+
+pub struct HighScorePlugin;
+
+impl Plugin for HighScorePlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .insert_resource(HighScores::load())
+            .init_resource::<NameEntry>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::GameOverMenu)
+                    .with_system(record_high_score_system.after(save_replay_system)),
+            );
+    }
+}
+
+/// How the run was played
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Mode {
+    Normal,
+    /// Dying went back to a savestate, see `practice`
+    Practice,
+}
+
+/// A finished run in the high score tables
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HighScore {
+    /// Seconds since the Unix epoch when the run ended
+    pub timestamp: u64,
+    pub seed: u64,
+    pub score: u32,
+    /// Distance from the spawn point
+    pub distance: f32,
+    pub mode: Mode,
+    /// Replay of the run, if one was saved
+    #[serde(default)]
+    pub replay: Option<PathBuf>,
+    /// Name or initials of the player, if they entered one
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl HighScore {
+    /// The run ending now
+    pub fn new(seed: u64, score: &Score, mode: Mode, replay: Option<PathBuf>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        Self {
+            timestamp,
+            seed,
+            score: score.total(),
+            distance: score.distance,
+            mode,
+            replay,
+            name: None,
+        }
+    }
+
+    /// Date the run ended on, as year-month-day in UTC
+    pub fn date(&self) -> String {
+        // Howard Hinnant's `civil_from_days`, with days counted from 0000-03-01
+        let days = (self.timestamp / 86_400) as i64 + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// Best runs of every seed, and of all seeds, for each mode
+#[derive(Default, Serialize, Deserialize)]
+pub struct HighScores {
+    /// Sorted by score, best first
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    /// Reads the saved high scores. A corrupt file is moved aside rather than overwritten by
+    /// the next save, and the tables start over empty.
+    pub fn load() -> Self {
+        HighScores::load_from(dirs::data_dir())
+    }
+
+    fn load_from(base: Option<PathBuf>) -> Self {
+        let mut high_scores: HighScores =
+            storage::load(base, HIGH_SCORES_FILE, "high scores").unwrap_or_default();
+        high_scores
+            .entries
+            .sort_by(|a, b| b.score.cmp(&a.score).then(a.timestamp.cmp(&b.timestamp)));
+        high_scores
+    }
+
+    pub fn save(&self) {
+        storage::save(dirs::data_dir(), HIGH_SCORES_FILE, "high scores", self);
+    }
+
+    /// Adds the run, and drops the runs no longer in the top `TABLE_SIZE` of either their seed
+    /// or of all seeds. Returns where the run is kept, if it made it into a table.
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        // ties rank below the runs that got there first
+        let rank = self
+            .entries
+            .partition_point(|other| other.score >= entry.score);
+        self.entries.insert(rank, entry);
+
+        let mut seed_counts: HashMap<(u64, Mode), usize> = HashMap::default();
+        let mut mode_counts: HashMap<Mode, usize> = HashMap::default();
+        let mut kept = Vec::with_capacity(self.entries.len());
+        let mut inserted = None;
+        for (i, entry) in self.entries.drain(..).enumerate() {
+            let seed_count = seed_counts.entry((entry.seed, entry.mode)).or_default();
+            let mode_count = mode_counts.entry(entry.mode).or_default();
+            *seed_count += 1;
+            *mode_count += 1;
+            if *seed_count <= TABLE_SIZE || *mode_count <= TABLE_SIZE {
+                if i == rank {
+                    inserted = Some(kept.len());
+                }
+                kept.push(entry);
+            }
+        }
+        self.entries = kept;
+        inserted
+    }
+
+    /// Names the run returned by `insert`
+    pub fn set_name(&mut self, index: usize, name: String) {
+        if let Some(entry) = self.entries.get_mut(index) {
+            entry.name = Some(name);
+        }
+    }
+
+    /// Replay of the best-scoring run on the seed, among the ones recorded with the
+    /// same tuning, as any other tuning plays differently. Only the runs in the tables are
+    /// looked at, so that finding it reads a few replay files rather than all of them.
+    pub fn personal_best(&self, seed: u64, tuning: &Tuning) -> Option<Replay> {
+        self.top(Some(seed), Mode::Normal)
+            .filter_map(|entry| Replay::load(entry.replay.as_ref()?).ok())
+            .find(|replay| replay.seed == seed && replay.tuning == *tuning)
+    }
+
+    /// Best runs in the mode, on the seed or on all seeds
+    pub fn top(&self, seed: Option<u64>, mode: Mode) -> impl Iterator<Item = &HighScore> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.mode == mode && seed.is_none_or(|seed| entry.seed == seed))
+            .take(TABLE_SIZE)
+    }
+}
+
+/// Name being entered on the game over screen for the run that made it into a table
+#[derive(Default)]
+pub struct NameEntry {
+    /// Where the run is in the high scores, while its name is being entered
+    pub index: Option<usize>,
+    pub name: String,
+}
+
+impl NameEntry {
+    /// Stores the name entered so far with the run, and ends the entry
+    pub fn commit(&mut self, high_scores: &mut HighScores) {
+        let index = match self.index.take() {
+            Some(val) => val,
+            None => return,
+        };
+        let name = self.name.trim();
+        if !name.is_empty() {
+            high_scores.set_name(index, name.to_string());
+            high_scores.save();
+        }
+    }
+}
+
+/// Lines of a high score table, one per run, or a note for an empty table
+pub fn table_text<'a>(entries: impl Iterator<Item = &'a HighScore>) -> String {
+    let lines: Vec<String> = entries
+        .enumerate()
+        .map(|(i, entry)| {
+            format!(
+                "{:>2}. {:>6}  {:>7.1}  {}  {}",
+                i + 1,
+                entry.score,
+                entry.distance,
+                entry.date(),
+                entry.name.as_deref().unwrap_or("")
+            )
+        })
+        .collect();
+    if lines.is_empty() {
+        "No runs yet".to_string()
+    } else {
+        lines.join("\n")
+    }
+}
+
+pub fn record_high_score_system(
+    seed_state: Res<State<SeedState>>,
+    score: Option<Res<Score>>,
+    saved_replay: Res<SavedReplay>,
+    mut high_scores: ResMut<HighScores>,
+    mut name_entry: ResMut<NameEntry>,
+) {
+    let score = match score {
+        Some(val) => val,
+        None => return,
+    };
+    let seed = seed_state.current().value;
+    let entry = HighScore::new(seed, &score, Mode::Normal, saved_replay.0.clone());
+    *name_entry = NameEntry::default();
+    if let Some(index) = high_scores.insert(entry) {
+        high_scores.save();
+        name_entry.index = Some(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(seed: u64, score: u32, mode: Mode, timestamp: u64) -> HighScore {
+        HighScore {
+            timestamp,
+            seed,
+            score,
+            distance: 0.0,
+            mode,
+            replay: None,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn ties_rank_below_earlier_runs() {
+        let mut high_scores = HighScores::default();
+        assert_eq!(high_scores.insert(run(1, 10, Mode::Normal, 1)), Some(0));
+        assert_eq!(high_scores.insert(run(1, 20, Mode::Normal, 2)), Some(0));
+        assert_eq!(high_scores.insert(run(1, 10, Mode::Normal, 3)), Some(2));
+        let timestamps: Vec<u64> = high_scores.entries.iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, [2, 1, 3]);
+    }
+
+    #[test]
+    fn tables_keep_the_best_of_each_seed_and_mode() {
+        let mut high_scores = HighScores::default();
+        for score in 100..=100 + TABLE_SIZE as u32 {
+            assert!(high_scores.insert(run(1, score, Mode::Normal, 0)).is_some());
+        }
+        // the worst run of the seed dropped out of both tables
+        assert_eq!(high_scores.top(Some(1), Mode::Normal).count(), TABLE_SIZE);
+        assert!(high_scores.entries.iter().all(|entry| entry.score > 100));
+        assert_eq!(high_scores.insert(run(1, 50, Mode::Normal, 0)), None);
+
+        // a run too low for all seeds still tops its own seed, and practice has its own tables
+        assert_eq!(
+            high_scores.insert(run(2, 1, Mode::Normal, 0)),
+            Some(TABLE_SIZE)
+        );
+        assert_eq!(
+            high_scores.insert(run(1, 1, Mode::Practice, 0)),
+            Some(TABLE_SIZE + 1)
+        );
+        assert_eq!(high_scores.top(None, Mode::Normal).count(), TABLE_SIZE);
+        assert_eq!(high_scores.top(Some(2), Mode::Normal).count(), 1);
+        assert_eq!(high_scores.top(Some(1), Mode::Practice).count(), 1);
+        let best_overall = high_scores.top(None, Mode::Normal).next().unwrap();
+        assert_eq!(best_overall.score, 100 + TABLE_SIZE as u32);
+    }
+
+    #[test]
+    fn dates_are_in_utc() {
+        let date = |timestamp| run(0, 0, Mode::Normal, timestamp).date();
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_700_000_000), "2023-11-14");
+        assert_eq!(date(4_102_444_799), "2099-12-31");
+        assert_eq!(date(4_107_542_400), "2100-03-01");
+    }
+
+    #[test]
+    fn saved_tables_load_sorted() {
+        let base = std::env::temp_dir().join(format!("jump-{}-highscores", std::process::id()));
+        let saved = HighScores {
+            entries: vec![run(1, 10, Mode::Normal, 1), run(1, 20, Mode::Normal, 2)],
+        };
+        storage::save(Some(base.clone()), HIGH_SCORES_FILE, "high scores", &saved);
+        let scores: Vec<u32> = HighScores::load_from(Some(base.clone()))
+            .entries
+            .iter()
+            .map(|entry| entry.score)
+            .collect();
+        assert_eq!(scores, [20, 10]);
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
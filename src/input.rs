@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+use super::GameState;
+
+/// File the key bindings are saved to, inside the game's config directory
+const BINDINGS_FILE: &str = "bindings.ron";
+
+/// How far the left stick must be pushed to count as a press
+pub const STICK_THRESHOLD: f32 = 0.5;
+
+/// Seconds a click or touch is held before it turns from a jump into a boost
+const HOLD_TO_BOOST: f32 = 0.25;
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .insert_resource(InputBindings::load())
+            .init_resource::<Input<Action>>()
+            .init_resource::<ActiveGamepad>()
+            // resolve the actions right after Bevy has read this frame's raw input
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                active_gamepad_system.after(InputSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                action_input_system
+                    .after(InputSystem)
+                    .after(active_gamepad_system),
+            );
+    }
+}
+
+/// Everything the player can do, independent of the key used for it.
+/// Gameplay systems read these from `Res<Input<Action>>` instead of the keyboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Jump,
+    Boost,
+    Down,
+    Pause,
+    /// Steps back in time, in practice mode
+    Rewind,
+    /// Saves the state of the run, in practice mode
+    SaveState,
+    /// Puts the run back to the saved state, in practice mode
+    LoadState,
+    /// Leaves a practice run or a replay playback for the main menu
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::Jump,
+        Action::Boost,
+        Action::Down,
+        Action::Pause,
+        Action::Rewind,
+        Action::SaveState,
+        Action::LoadState,
+        Action::Quit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Jump => "Jump",
+            Action::Boost => "Boost",
+            Action::Down => "Duck",
+            Action::Pause => "Pause",
+            Action::Rewind => "Rewind",
+            Action::SaveState => "Save state",
+            Action::LoadState => "Load state",
+            Action::Quit => "Quit",
+        }
+    }
+}
+
+/// Key and gamepad button bound to each action, saved in the user's config directory
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InputBindings {
+    #[serde(default)]
+    keys: BTreeMap<Action, KeyCode>,
+    #[serde(default)]
+    buttons: BTreeMap<Action, GamepadButtonType>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        Self {
+            keys: BTreeMap::from([
+                (Action::Jump, KeyCode::Space),
+                (Action::Boost, KeyCode::Right),
+                (Action::Down, KeyCode::Down),
+                (Action::Pause, KeyCode::Escape),
+                (Action::Rewind, KeyCode::R),
+                (Action::SaveState, KeyCode::F5),
+                (Action::LoadState, KeyCode::F9),
+                (Action::Quit, KeyCode::Back),
+            ]),
+            buttons: BTreeMap::from([
+                (Action::Jump, GamepadButtonType::South),
+                (Action::Boost, GamepadButtonType::RightTrigger2),
+                (Action::Down, GamepadButtonType::DPadDown),
+                (Action::Pause, GamepadButtonType::Start),
+                (Action::Rewind, GamepadButtonType::LeftTrigger),
+                (Action::SaveState, GamepadButtonType::North),
+                (Action::LoadState, GamepadButtonType::West),
+                (Action::Quit, GamepadButtonType::Select),
+            ]),
+        }
+    }
+}
+
+impl InputBindings {
+    pub fn key(&self, action: Action) -> Option<KeyCode> {
+        self.keys.get(&action).copied()
+    }
+
+    /// Binds the key to the action, unless the key is already bound to another action.
+    /// On a conflict the bindings stay unchanged, and the conflicting action is returned.
+    pub fn rebind(&mut self, action: Action, key: KeyCode) -> Result<(), Action> {
+        let conflict = self
+            .keys
+            .iter()
+            .find(|&(&other, &other_key)| other != action && other_key == key);
+        if let Some((&other, _)) = conflict {
+            return Err(other);
+        }
+        self.keys.insert(action, key);
+        Ok(())
+    }
+
+    pub fn button(&self, action: Action) -> Option<GamepadButtonType> {
+        self.buttons.get(&action).copied()
+    }
+
+    /// Same as `rebind`, for gamepad buttons
+    pub fn rebind_button(
+        &mut self,
+        action: Action,
+        button: GamepadButtonType,
+    ) -> Result<(), Action> {
+        let conflict = self
+            .buttons
+            .iter()
+            .find(|&(&other, &other_button)| other != action && other_button == button);
+        if let Some((&other, _)) = conflict {
+            return Err(other);
+        }
+        self.buttons.insert(action, button);
+        Ok(())
+    }
+
+    /// Loads the saved bindings, falling back to the defaults for anything missing. A corrupt
+    /// file is moved aside like the high scores.
+    pub fn load() -> Self {
+        let saved: Option<InputBindings> =
+            storage::load(dirs::config_dir(), BINDINGS_FILE, "bindings");
+        match saved {
+            Some(saved) => InputBindings::with_saved(saved),
+            None => InputBindings::default(),
+        }
+    }
+
+    // The defaults with the saved bindings on top, such as bindings saved before an action
+    // was added
+    fn with_saved(saved: InputBindings) -> Self {
+        let mut bindings = InputBindings::default();
+        merge_bindings(&mut bindings.keys, saved.keys);
+        merge_bindings(&mut bindings.buttons, saved.buttons);
+        bindings
+    }
+
+    pub fn save(&self) {
+        storage::save(dirs::config_dir(), BINDINGS_FILE, "bindings", self);
+    }
+}
+
+// Puts the saved bindings over the defaults. A default using a key or button that was saved for
+// another action is left unbound rather than shared, for the player to bind on the controls screen.
+fn merge_bindings<T: Copy + PartialEq + std::fmt::Debug>(
+    defaults: &mut BTreeMap<Action, T>,
+    saved: BTreeMap<Action, T>,
+) {
+    defaults.retain(|&action, &mut default| {
+        let taken_by = saved
+            .iter()
+            .find(|&(&other, &bound)| other != action && bound == default);
+        match taken_by {
+            Some((other, _)) if !saved.contains_key(&action) => {
+                warn!(
+                    "{} is unbound, its default {:?} is bound to {}",
+                    action.name(),
+                    default,
+                    other.name()
+                );
+                false
+            }
+            _ => true,
+        }
+    });
+    defaults.extend(saved);
+}
+
+/// Human readable name of a key, for the menus
+pub fn key_name(key: Option<KeyCode>) -> String {
+    match key {
+        Some(key) => format!("{:?}", key),
+        None => "unbound".to_string(),
+    }
+}
+
+/// Human readable name of a gamepad button, for the menus
+pub fn button_name(button: Option<GamepadButtonType>) -> String {
+    match button {
+        Some(button) => format!("{:?}", button),
+        None => "unbound".to_string(),
+    }
+}
+
+/// Gamepad driving the game, the first one connected that is still plugged in
+#[derive(Default)]
+pub struct ActiveGamepad(pub Option<Gamepad>);
+
+// Picks up gamepads as they are plugged in, and falls back to another one when the active one is unplugged
+fn active_gamepad_system(
+    mut gamepad_events: EventReader<GamepadEvent>,
+    gamepads: Res<Gamepads>,
+    mut active: ResMut<ActiveGamepad>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected if active.0.is_none() => {
+                info!("Using {:?} for input", gamepad);
+                active.0 = Some(*gamepad);
+            }
+            GamepadEventType::Disconnected if active.0 == Some(*gamepad) => {
+                active.0 = gamepads.iter().find(|&other| other != gamepad).copied();
+                match active.0 {
+                    Some(other) => info!("{:?} unplugged, using {:?} for input", gamepad, other),
+                    None => info!("{:?} unplugged, no gamepad left", gamepad),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Mouse and touch input of a frame
+#[derive(Clone, Copy, Default)]
+struct Pointer {
+    /// Left mouse button or a finger down
+    down: bool,
+    /// Left mouse button or a finger went down on this frame
+    just_pressed: bool,
+    /// Fingers down
+    touches: usize,
+}
+
+impl Pointer {
+    fn read(mouse_input: &Input<MouseButton>, touches: &Touches) -> Self {
+        let touch_count = touches.iter().count();
+        Self {
+            down: mouse_input.pressed(MouseButton::Left) || touch_count > 0,
+            just_pressed: mouse_input.just_pressed(MouseButton::Left)
+                || touches.iter_just_pressed().next().is_some(),
+            touches: touch_count,
+        }
+    }
+}
+
+// Clicking or tapping anywhere jumps, holding it boosts, and so does a second finger.
+// Only a press made during a run counts, so that the click on a menu button starting the
+// run is not taken for a jump. `held_for` is how long that press has been held, if any.
+fn pointer_actions(
+    pointer: Pointer,
+    in_run: bool,
+    held_for: &mut Option<f32>,
+    delta_seconds: f32,
+) -> (bool, bool) {
+    if !pointer.down || !in_run {
+        *held_for = None;
+        return (false, false);
+    }
+    if pointer.just_pressed && held_for.is_none() {
+        *held_for = Some(0.0);
+    }
+    let held_for = match held_for {
+        Some(val) => val,
+        None => return (false, false),
+    };
+    *held_for += delta_seconds;
+    let jump = *held_for <= HOLD_TO_BOOST;
+    let boost = !jump || pointer.touches > 1;
+    (jump, boost)
+}
+
+fn action_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    touches: Res<Touches>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    bindings: Res<InputBindings>,
+    active_gamepad: Res<ActiveGamepad>,
+    time: Res<Time>,
+    game_state: Res<State<GameState>>,
+    mut pointer_held_for: Local<Option<f32>>,
+    mut actions: ResMut<Input<Action>>,
+) {
+    actions.clear();
+    let in_run = matches!(game_state.current(), GameState::Game | GameState::Playback);
+    let (pointer_jump, pointer_boost) = pointer_actions(
+        Pointer::read(&mouse_input, &touches),
+        in_run,
+        &mut pointer_held_for,
+        time.delta_seconds(),
+    );
+    for action in Action::ALL {
+        let mut pressed = bindings
+            .key(action)
+            .is_some_and(|key| keyboard_input.pressed(key));
+        if let Some(gamepad) = active_gamepad.0 {
+            pressed |= bindings
+                .button(action)
+                .is_some_and(|button| gamepad_buttons.pressed(GamepadButton(gamepad, button)));
+            // the left stick doubles the d-pad
+            let stick = |axis| gamepad_axes.get(GamepadAxis(gamepad, axis)).unwrap_or(0.0);
+            pressed |= match action {
+                Action::Boost => stick(GamepadAxisType::LeftStickX) > STICK_THRESHOLD,
+                Action::Down => stick(GamepadAxisType::LeftStickY) < -STICK_THRESHOLD,
+                _ => false,
+            };
+        }
+        pressed |= match action {
+            Action::Jump => pointer_jump,
+            Action::Boost => pointer_boost,
+            _ => false,
+        };
+        if pressed {
+            actions.press(action);
+        } else {
+            actions.release(action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f32 = 0.1;
+
+    #[test]
+    fn rebinding_to_a_used_key_is_a_conflict() {
+        let mut bindings = InputBindings::default();
+        assert_eq!(
+            bindings.rebind(Action::Jump, KeyCode::R),
+            Err(Action::Rewind)
+        );
+        assert_eq!(bindings.key(Action::Jump), Some(KeyCode::Space));
+        assert_eq!(bindings.key(Action::Rewind), Some(KeyCode::R));
+        // rebinding an action to its own key is no conflict
+        assert_eq!(bindings.rebind(Action::Jump, KeyCode::Space), Ok(()));
+        assert_eq!(bindings.rebind(Action::Jump, KeyCode::Up), Ok(()));
+        assert_eq!(bindings.key(Action::Jump), Some(KeyCode::Up));
+    }
+
+    #[test]
+    fn rebinding_to_a_used_button_is_a_conflict() {
+        let mut bindings = InputBindings::default();
+        let taken = GamepadButtonType::Select;
+        assert_eq!(
+            bindings.rebind_button(Action::Jump, taken),
+            Err(Action::Quit)
+        );
+        assert_eq!(
+            bindings.button(Action::Jump),
+            Some(GamepadButtonType::South)
+        );
+        let free = GamepadButtonType::East;
+        assert_eq!(bindings.rebind_button(Action::Jump, free), Ok(()));
+        assert_eq!(bindings.button(Action::Jump), Some(free));
+    }
+
+    #[test]
+    fn saved_bindings_win_over_new_defaults() {
+        // saved before Rewind and Quit had keys, with their default keys in use
+        let saved = InputBindings {
+            keys: BTreeMap::from([(Action::Jump, KeyCode::R), (Action::Pause, KeyCode::Back)]),
+            buttons: BTreeMap::from([(Action::Jump, GamepadButtonType::LeftTrigger)]),
+        };
+        let bindings = InputBindings::with_saved(saved);
+        assert_eq!(bindings.key(Action::Jump), Some(KeyCode::R));
+        assert_eq!(bindings.key(Action::Rewind), None);
+        assert_eq!(bindings.key(Action::Pause), Some(KeyCode::Back));
+        assert_eq!(bindings.key(Action::Quit), None);
+        assert_eq!(bindings.key(Action::Boost), Some(KeyCode::Right));
+        assert_eq!(bindings.button(Action::Rewind), None);
+
+        // no key is left bound to two actions
+        for action in Action::ALL {
+            let key = bindings.key(action);
+            let shared = Action::ALL
+                .iter()
+                .any(|&other| other != action && key.is_some() && bindings.key(other) == key);
+            assert!(!shared, "{:?} shares its key", action);
+        }
+    }
+
+    #[test]
+    fn saved_bindings_keep_their_own_defaults() {
+        // the saved Rewind key frees R for Jump, there is no conflict
+        let saved = InputBindings {
+            keys: BTreeMap::from([(Action::Jump, KeyCode::R), (Action::Rewind, KeyCode::T)]),
+            buttons: BTreeMap::new(),
+        };
+        let bindings = InputBindings::with_saved(saved);
+        assert_eq!(bindings.key(Action::Jump), Some(KeyCode::R));
+        assert_eq!(bindings.key(Action::Rewind), Some(KeyCode::T));
+    }
+
+    fn press(touches: usize) -> Pointer {
+        Pointer {
+            down: true,
+            just_pressed: true,
+            touches,
+        }
+    }
+
+    fn hold(touches: usize) -> Pointer {
+        Pointer {
+            just_pressed: false,
+            ..press(touches)
+        }
+    }
+
+    #[test]
+    fn press_jumps_then_boosts_while_held() {
+        let mut held_for = None;
+        assert_eq!(
+            pointer_actions(press(0), true, &mut held_for, FRAME),
+            (true, false)
+        );
+        assert_eq!(
+            pointer_actions(hold(0), true, &mut held_for, FRAME),
+            (true, false)
+        );
+        assert_eq!(
+            pointer_actions(hold(0), true, &mut held_for, FRAME),
+            (false, true)
+        );
+        let released = Pointer::default();
+        assert_eq!(
+            pointer_actions(released, true, &mut held_for, FRAME),
+            (false, false)
+        );
+        assert_eq!(held_for, None);
+    }
+
+    #[test]
+    fn second_finger_boosts() {
+        let mut held_for = None;
+        assert_eq!(
+            pointer_actions(press(1), true, &mut held_for, FRAME),
+            (true, false)
+        );
+        assert_eq!(
+            pointer_actions(press(2), true, &mut held_for, FRAME),
+            (true, true)
+        );
+    }
+
+    #[test]
+    fn press_from_before_the_run_is_ignored() {
+        let mut held_for = None;
+        // the click on the menu button that starts the run
+        assert_eq!(
+            pointer_actions(press(0), false, &mut held_for, FRAME),
+            (false, false)
+        );
+        for _ in 0..5 {
+            assert_eq!(
+                pointer_actions(hold(0), true, &mut held_for, FRAME),
+                (false, false)
+            );
+        }
+        // a new press after letting go counts
+        pointer_actions(Pointer::default(), true, &mut held_for, FRAME);
+        assert_eq!(
+            pointer_actions(press(0), true, &mut held_for, FRAME),
+            (true, false)
+        );
+    }
+}
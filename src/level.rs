@@ -1,9 +1,36 @@
 use bevy::{pbr::StandardMaterial, prelude::Color};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::speed::SpeedCurve;
+
+/// Version of the level generator, bumped whenever the same seed starts generating a
+/// different level, so that replays of older levels can be told apart
+pub const GENERATOR_VERSION: u32 = 1;
+/// Where the player starts each level
+pub const SPAWN_X: f32 = -5.0;
+/// Height the player rolls at along the ceiling inside gravity zones, mirroring the ground at 0
+pub const CEILING_HEIGHT: f32 = 4.0;
 
 const OBSTACLE_COUNT: u32 = 40;
+/// Obstacles start after the buffer area around the spawn point
+const FIRST_OBSTACLE_X: f32 = 1.0;
+/// Range of the gaps between consecutive obstacles at the starting scroll speed
+const MIN_OBSTACLE_GAP: f32 = 2.5;
+const MAX_OBSTACLE_GAP: f32 = 7.5;
 const LIGHT_COUNT: u32 = 150;
+const PICKUP_COUNT: u32 = 15;
+/// Pickups float at heights reachable with a jump
+const PICKUP_MAX_Y: f32 = 2.0;
+/// Range of the lengths of the gravity zones, and of the distances between them
+const MIN_GRAVITY_ZONE_LENGTH: f32 = 15.0;
+const MAX_GRAVITY_ZONE_LENGTH: f32 = 30.0;
+const MIN_GRAVITY_ZONE_GAP: f32 = 30.0;
+const MAX_GRAVITY_ZONE_GAP: f32 = 60.0;
+/// Distance after entering or leaving a gravity zone that is kept clear of obstacles,
+/// as the player is still falling towards the new floor
+const GRAVITY_ZONE_MARGIN: f32 = 8.0;
 const LEVEL_MIN_X: i32 = -10;
 const LEVEL_MAX_X: i32 = 200;
 
@@ -13,14 +40,16 @@ pub struct Level {
     pub obstacles: Vec<Obstacle>,
     /// List of coordinates of the lights
     pub lights: Vec<(f32, f32)>,
-    /// Seed used for generating this level
-    pub seed: u64,
     /// Background objects
     pub bg_objects: Vec<BgObject>,
+    /// List of coordinates of the boost energy pickups
+    pub pickups: Vec<(f32, f32)>,
+    /// List of start and end coordinates of the zones with flipped gravity
+    pub gravity_zones: Vec<(f32, f32)>,
 }
 
 impl Level {
-    pub fn new(seed: u64) -> Level {
+    pub fn new(seed: u64, speed_curve: &SpeedCurve) -> Level {
         // "ChaCha8Rng is an excellent choice for a deterministic master generator"
         // https://rust-random.github.io/book/guide-seeding.html
         let mut rng = ChaCha8Rng::seed_from_u64(seed);
@@ -28,16 +57,57 @@ impl Level {
         let mut obstacles = Vec::new();
         let mut lights = Vec::new();
         let mut bg_objects = Vec::new();
+        let mut pickups = Vec::new();
+        let mut gravity_zones = Vec::new();
+
+        // Gravity zones
+        let mut x = FIRST_OBSTACLE_X;
+        loop {
+            let start = x + rng.gen_range(MIN_GRAVITY_ZONE_GAP..MAX_GRAVITY_ZONE_GAP);
+            let end = start + rng.gen_range(MIN_GRAVITY_ZONE_LENGTH..MAX_GRAVITY_ZONE_LENGTH);
+            if end >= LEVEL_MAX_X as f32 {
+                break;
+            }
+            gravity_zones.push((start, end));
+            x = end;
+        }
+        let flipped = |x: f32| in_gravity_zone(&gravity_zones, x);
+        let near_zone_edge = |x: f32| {
+            gravity_zones.iter().any(|&(start, end)| {
+                (start..start + GRAVITY_ZONE_MARGIN).contains(&x)
+                    || (end..end + GRAVITY_ZONE_MARGIN).contains(&x)
+            })
+        };
 
-        // Obstacles
+        // Obstacles, with gaps that grow with the scroll speed so there is as much time to react
+        let mut x = FIRST_OBSTACLE_X;
         for _ in 0..OBSTACLE_COUNT {
             // TODO: better location algorithm for making sure every level is winnable
-            let x: f32 = rng.gen_range(1.0..(LEVEL_MAX_X as f32));
-            let y: f32 = rng.gen_range(0.0..1.0);
+            let gap: f32 = rng.gen_range(MIN_OBSTACLE_GAP..MAX_OBSTACLE_GAP);
+            x += gap * speed_curve.speed_factor(x - SPAWN_X);
+            if x >= LEVEL_MAX_X as f32 {
+                break;
+            }
+            let mut y: f32 = rng.gen_range(0.0..1.0);
 
             let material = random_material(&mut rng);
 
-            let obstacle = Obstacle { x, y, material };
+            if near_zone_edge(x) {
+                continue;
+            }
+            // hanging from the ceiling inside gravity zones
+            let kind = if flipped(x) {
+                y = CEILING_HEIGHT - y;
+                ObstacleKind::Ceiling
+            } else {
+                ObstacleKind::Ground
+            };
+            let obstacle = Obstacle {
+                x,
+                y,
+                kind,
+                material,
+            };
             obstacles.push(obstacle);
         }
 
@@ -60,18 +130,45 @@ impl Level {
             }
         }
 
+        // Pickups
+        for _ in 0..PICKUP_COUNT {
+            let x: f32 = rng.gen_range(FIRST_OBSTACLE_X..(LEVEL_MAX_X as f32));
+            let mut y: f32 = rng.gen_range(0.0..PICKUP_MAX_Y);
+            if flipped(x) {
+                y = CEILING_HEIGHT - y;
+            }
+            pickups.push((x, y));
+        }
+
         Level {
             obstacles,
             lights,
-            seed,
             bg_objects,
+            pickups,
+            gravity_zones,
         }
     }
 }
 
+/// Whether gravity is flipped at the given x coordinate
+pub fn in_gravity_zone(gravity_zones: &[(f32, f32)], x: f32) -> bool {
+    gravity_zones
+        .iter()
+        .any(|&(start, end)| (start..end).contains(&x))
+}
+
+/// Where an obstacle stands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ObstacleKind {
+    Ground,
+    /// Hanging from the ceiling of a gravity zone
+    Ceiling,
+}
+
 pub struct Obstacle {
     pub x: f32,
     pub y: f32,
+    pub kind: ObstacleKind,
     pub material: StandardMaterial,
 }
 
@@ -1,13 +1,36 @@
 #![allow(clippy::type_complexity)] // Bevy has complex types
+#![allow(clippy::too_many_arguments)] // Bevy systems take their resources as arguments
+#![allow(clippy::default_constructed_unit_structs)] // plugins are added as in the Bevy examples
 
+use bevy::asset::AssetServerSettings;
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
 use bevy::prelude::*;
 
 use rand::prelude::*;
+use std::path::Path;
+use std::process;
 
+use replay::{Playback, Replay};
+use tuning::{Tuning, TuningOverride};
+
+mod clock;
+mod collision;
+#[cfg(debug_assertions)]
+mod debug;
 mod game;
+mod highscore;
+mod input;
 mod level;
 mod menu;
+mod physics;
+mod playback;
+mod practice;
+mod replay;
+mod speed;
+mod stats;
+mod storage;
+mod tuning;
+mod verify;
 
 // Enum that will be used as a global state for the game
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
@@ -15,6 +38,7 @@ enum GameState {
     MainMenu,
     Game,
     GameOverMenu,
+    Playback,
 }
 
 // The seed used for the level generation
@@ -23,20 +47,74 @@ struct SeedState {
     pub value: u64,
 }
 
+const USAGE: &str =
+    "usage: jump [--no-state-hashes] [replay <file> | verify [--any-tuning] <file or directory>...]";
+
 fn main() {
     let mut rng = ThreadRng::default();
-    let seed: u64 = rng.gen();
+    let mut seed: u64 = rng.gen();
+    let mut initial_state = GameState::MainMenu;
+
+    let mut app = App::new();
+    // `jump replay <file>` plays a replay back instead of showing the main menu,
+    // `jump verify <files>` checks replays without opening a window
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // `--no-state-hashes` leaves the per-tick state hashes out of replays and playbacks
+    if let Some(i) = args.iter().position(|arg| arg == "--no-state-hashes") {
+        args.remove(i);
+        app.insert_resource(game::StateHashing(false));
+    }
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => {}
+        ["replay", path] => {
+            let replay = match Replay::load(Path::new(path)) {
+                Ok(val) => val,
+                Err(error) => {
+                    eprintln!("Could not load replay {}: {}", path, error);
+                    process::exit(2);
+                }
+            };
+            seed = replay.seed;
+            initial_state = GameState::Playback;
+            // the replay is simulated with the tuning it was recorded with
+            app.insert_resource::<Tuning>(replay.tuning.clone())
+                .insert_resource(TuningOverride(Some(replay.tuning.clone())))
+                .insert_resource(Playback::new(replay));
+        }
+        ["verify", "--any-tuning", ref paths @ ..] if !paths.is_empty() => {
+            process::exit(verify::run(paths, true))
+        }
+        ["verify", ref paths @ ..] if !paths.is_empty() => process::exit(verify::run(paths, false)),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
 
-    App::new()
+    app // load-bearing comment, better readability for chains below
+        // hot reload the assets, most importantly the gameplay tuning file
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true,
+            ..default()
+        })
         .add_plugins(DefaultPlugins)
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .add_startup_system(setup)
-        .add_state(GameState::MainMenu)
+        .add_state(initial_state)
         .add_state(SeedState { value: seed })
+        .add_plugin(tuning::TuningPlugin)
+        .add_plugin(input::ActionPlugin)
+        .add_plugin(collision::CollisionPlugin)
         .add_plugin(menu::MainMenuPlugin)
         .add_plugin(game::GamePlugin)
-        // .add_plugin(game::PauseMenuPlugin)
-        .run();
+        .add_plugin(highscore::HighScorePlugin)
+        .add_plugin(playback::PlaybackPlugin)
+        .add_plugin(practice::PracticePlugin)
+        .add_plugin(stats::StatsPlugin);
+    // .add_plugin(game::PauseMenuPlugin)
+    #[cfg(debug_assertions)]
+    app.add_plugin(debug::DebugPlugin);
+    app.run();
 }
 
 fn setup(mut commands: Commands) {
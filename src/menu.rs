@@ -1,6 +1,14 @@
 use super::{despawn_screen, GameState, SeedState};
+use crate::highscore::{
+    record_high_score_system, table_text, HighScores, Mode, NameEntry, NAME_LENGTH,
+};
+use crate::input::{button_name, key_name, Action, ActiveGamepad, InputBindings, STICK_THRESHOLD};
+use crate::physics::Score;
+use crate::practice::Practice;
+use crate::stats::Statistics;
 use bevy::app::AppExit;
 use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
 use rand::prelude::*;
 pub struct MainMenuPlugin;
 
@@ -17,6 +25,9 @@ const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
 /// Fixed testing seed, used in place of proper user-provided seed loading mechanism
 const FIXED_RNG_SEED: u64 = 0x1234_5678;
 
+/// Characters a name is spelled with on a gamepad, in the order the d-pad cycles through them
+const NAME_CHARACTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 ";
+
 impl Plugin for MainMenuPlugin {
     fn build(&self, app: &mut App) {
         app
@@ -44,24 +55,62 @@ impl Plugin for MainMenuPlugin {
             .add_system_set(
                 SystemSet::on_exit(MenuState::Help).with_system(despawn_screen::<OnHelpMenuScreen>),
             )
+            // Systems to handle the statistics screen
+            .add_system_set(
+                SystemSet::on_enter(MenuState::Statistics).with_system(statistics_menu_setup),
+            )
+            .add_system_set(
+                SystemSet::on_exit(MenuState::Statistics)
+                    .with_system(despawn_screen::<OnStatisticsMenuScreen>),
+            )
+            // Systems to handle the controls screen
+            .init_resource::<Rebinding>()
+            .add_system_set(
+                SystemSet::on_enter(MenuState::Controls).with_system(controls_menu_setup),
+            )
+            .add_system_set(
+                SystemSet::on_exit(MenuState::Controls)
+                    .with_system(despawn_screen::<OnControlsMenuScreen>),
+            )
+            .add_system_set(
+                SystemSet::on_update(MenuState::Controls)
+                    // before `menu_action`, so that the button press starting a rebind is not captured
+                    .with_system(rebind_capture_system.before(menu_action))
+                    .with_system(binding_text_system.after(rebind_capture_system)),
+            )
             // Systems to handle the game over screen
             .add_system_set(
-                SystemSet::on_enter(MenuState::GameOver).with_system(game_over_menu_setup),
+                SystemSet::on_enter(MenuState::GameOver)
+                    // the tables shown include the run that just ended
+                    .with_system(game_over_menu_setup.after(record_high_score_system)),
             )
             .add_system_set(
                 SystemSet::on_exit(MenuState::GameOver)
-                    .with_system(despawn_screen::<OnGameOverMenuScreen>),
+                    .with_system(despawn_screen::<OnGameOverMenuScreen>)
+                    .with_system(name_entry_exit),
+            )
+            .add_system_set(
+                SystemSet::on_update(MenuState::GameOver)
+                    // before the gamepad navigation, which is off while entering a name
+                    .with_system(
+                        name_entry_system
+                            .before(gamepad_navigation_system)
+                            .before(menu_action),
+                    )
+                    .with_system(name_entry_text_system.after(name_entry_system)),
             )
             // Common systems to all screens that handles buttons behaviour
             .add_system_set(
                 SystemSet::on_update(GameState::MainMenu)
                     .with_system(menu_action)
-                    .with_system(button_system),
+                    .with_system(button_system)
+                    .with_system(gamepad_navigation_system.before(menu_action)),
             )
             .add_system_set(
                 SystemSet::on_update(GameState::GameOverMenu)
                     .with_system(menu_action)
-                    .with_system(button_system),
+                    .with_system(button_system)
+                    .with_system(gamepad_navigation_system.before(menu_action)),
             );
     }
 }
@@ -71,6 +120,8 @@ impl Plugin for MainMenuPlugin {
 pub(crate) enum MenuState {
     MainMenu,
     Help,
+    Statistics,
+    Controls,
     LoadMenu,
     GameOver,
     Disabled,
@@ -84,6 +135,14 @@ struct OnMainMenuScreen;
 #[derive(Component)]
 struct OnHelpMenuScreen;
 
+// Tag component used to tag entities added on the statistics menu screen
+#[derive(Component)]
+struct OnStatisticsMenuScreen;
+
+// Tag component used to tag entities added on the controls menu screen
+#[derive(Component)]
+struct OnControlsMenuScreen;
+
 // Tag component used to tag entities added on the game over menu screen
 #[derive(Component)]
 struct OnGameOverMenuScreen;
@@ -96,31 +155,151 @@ struct OnLoadGameScreen;
 #[derive(Component)]
 struct SelectedOption;
 
+// Tag component used to mark the button selected with the gamepad
+#[derive(Component)]
+struct GamepadFocus;
+
+// Text of the button showing the key bound to an action
+#[derive(Component)]
+struct BindingText(Action);
+
+// Text of the name being entered for a new high score
+#[derive(Component)]
+struct NameEntryText;
+
+// Text explaining the state of rebinding on the controls screen
+#[derive(Component)]
+struct RebindStatusText;
+
+// Action waiting for a new key on the controls screen, and the outcome of the last rebind
+#[derive(Default)]
+struct Rebinding {
+    action: Option<Action>,
+    status: String,
+}
+
 // All actions that can be triggered from a button click
 #[derive(Component)]
 enum MenuButtonAction {
     NewGame,
+    Practice,
     Help,
+    Statistics,
+    Controls,
+    Rebind(Action),
     LoadMenu,
     BackToMainMenu,
     Quit,
     Thanks,
 }
 
+// Color of a button, the gamepad focus looks the same as a mouse hover
+fn button_color(interaction: Interaction, selected: bool, focused: bool) -> UiColor {
+    let hovered = interaction == Interaction::Hovered || focused;
+    match (interaction, hovered, selected) {
+        (Interaction::Clicked, _, _) => PRESSED_BUTTON.into(),
+        (_, true, true) => HOVERED_PRESSED_BUTTON.into(),
+        (_, true, false) => HOVERED_BUTTON.into(),
+        (_, false, true) => PRESSED_BUTTON.into(),
+        (_, false, false) => NORMAL_BUTTON.into(),
+    }
+}
+
 // This system handles changing all buttons color based on mouse interaction
 fn button_system(
     mut interaction_query: Query<
-        (&Interaction, &mut UiColor, Option<&SelectedOption>),
+        (
+            &Interaction,
+            &mut UiColor,
+            Option<&SelectedOption>,
+            Option<&GamepadFocus>,
+        ),
         (Changed<Interaction>, With<Button>),
     >,
 ) {
-    for (interaction, mut color, selected) in interaction_query.iter_mut() {
-        *color = match (*interaction, selected) {
-            (Interaction::Clicked, _) => PRESSED_BUTTON.into(),
-            (Interaction::Hovered, Some(_)) => HOVERED_PRESSED_BUTTON.into(),
-            (Interaction::Hovered, None) => HOVERED_BUTTON.into(),
-            (Interaction::None, Some(_)) => PRESSED_BUTTON.into(),
-            (Interaction::None, None) => NORMAL_BUTTON.into(),
+    for (interaction, mut color, selected, focused) in interaction_query.iter_mut() {
+        *color = button_color(*interaction, selected.is_some(), focused.is_some());
+    }
+}
+
+// Moves the gamepad focus between the buttons of the current screen, from top to bottom.
+// The first press only shows the focus on the top button.
+fn gamepad_navigation_system(
+    mut commands: Commands,
+    active_gamepad: Res<ActiveGamepad>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    rebinding: Res<Rebinding>,
+    name_entry: Res<NameEntry>,
+    mut stick_was_pushed: Local<bool>,
+    mut button_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &Interaction,
+            &mut UiColor,
+            Option<&SelectedOption>,
+            Option<&GamepadFocus>,
+        ),
+        With<MenuButtonAction>,
+    >,
+) {
+    let gamepad = match active_gamepad.0 {
+        Some(val) => val,
+        None => return,
+    };
+    // the d-pad picks the new binding while rebinding, and spells the name of a high score
+    if rebinding.action.is_some() || name_entry.index.is_some() {
+        return;
+    }
+    let stick_y = gamepad_axes
+        .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY))
+        .unwrap_or(0.0);
+    let stick_pushed = stick_y.abs() > STICK_THRESHOLD;
+    let stick_just_pushed = stick_pushed && !*stick_was_pushed;
+    *stick_was_pushed = stick_pushed;
+
+    let step: isize = if gamepad_buttons
+        .just_pressed(GamepadButton(gamepad, GamepadButtonType::DPadUp))
+        || (stick_just_pushed && stick_y > 0.0)
+    {
+        -1
+    } else if gamepad_buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::DPadDown))
+        || (stick_just_pushed && stick_y < 0.0)
+    {
+        1
+    } else {
+        return;
+    };
+
+    // UI coordinates grow upwards, so the top button has the highest y
+    let mut buttons: Vec<(Entity, Vec3, bool)> = button_query
+        .iter()
+        .map(|(entity, transform, _, _, _, focused)| {
+            (entity, transform.translation, focused.is_some())
+        })
+        .collect();
+    if buttons.is_empty() {
+        return;
+    }
+    buttons.sort_by(|(_, a, _), (_, b, _)| {
+        b.y.partial_cmp(&a.y)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal))
+    });
+    let next = match buttons.iter().position(|&(_, _, focused)| focused) {
+        Some(current) => (current as isize + step).rem_euclid(buttons.len() as isize) as usize,
+        None => 0,
+    };
+
+    for (index, &(entity, _, focused)) in buttons.iter().enumerate() {
+        let (_, _, interaction, mut color, selected, _) = button_query.get_mut(entity).unwrap();
+        if index == next {
+            commands.entity(entity).insert(GamepadFocus);
+            *color = button_color(*interaction, selected.is_some(), true);
+        } else if focused {
+            commands.entity(entity).remove::<GamepadFocus>();
+            *color = button_color(*interaction, selected.is_some(), false);
         }
     }
 }
@@ -129,12 +308,16 @@ fn menu_setup(mut menu_state: ResMut<State<MenuState>>) {
     let _ = menu_state.set(MenuState::MainMenu);
 }
 
-fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn main_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>,
+) {
     let font = asset_server.load("fonts/undefined-medium.ttf");
     // Common style for all buttons on the screen
     let button_style = Style {
         size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
-        margin: Rect::all(Val::Px(20.0)),
+        margin: Rect::all(Val::Px(6.0)),
         justify_content: JustifyContent::Center,
         align_items: AlignItems::Center,
         ..default()
@@ -177,10 +360,13 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 ..default()
             });
 
-            // Display four buttons for each action available from the main menu:
+            // Display seven buttons for each action available from the main menu:
             // - new game
+            // - practice
             // - load game
             // - help
+            // - statistics
+            // - controls
             // - quit
             parent
                 .spawn_bundle(ButtonBundle {
@@ -199,6 +385,23 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                         ..default()
                     });
                 });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: button_style.clone(),
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::Practice)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::with_section(
+                            "Practice",
+                            button_text_style.clone(),
+                            Default::default(),
+                        ),
+                        ..default()
+                    });
+                });
             parent
                 .spawn_bundle(ButtonBundle {
                     style: button_style.clone(),
@@ -233,6 +436,40 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                         ..default()
                     });
                 });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: button_style.clone(),
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::Statistics)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::with_section(
+                            "Statistics",
+                            button_text_style.clone(),
+                            Default::default(),
+                        ),
+                        ..default()
+                    });
+                });
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: button_style.clone(),
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::Controls)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::with_section(
+                            "Controls",
+                            button_text_style.clone(),
+                            Default::default(),
+                        ),
+                        ..default()
+                    });
+                });
             parent
                 .spawn_bundle(ButtonBundle {
                     style: button_style,
@@ -251,9 +488,41 @@ fn main_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                     });
                 });
         });
+
+    // best runs of all levels, next to the buttons
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(TEXT_MARGIN),
+                    right: Val::Px(TEXT_MARGIN),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::with_section(
+                format!(
+                    "High scores\n{}",
+                    table_text(high_scores.top(None, Mode::Normal))
+                ),
+                TextStyle {
+                    font,
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..default()
+        })
+        .insert(OnMainMenuScreen);
 }
 
-fn help_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn help_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    bindings: Res<InputBindings>,
+) {
     let button_style = Style {
         size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
         margin: Rect::all(Val::Px(20.0)),
@@ -286,7 +555,13 @@ fn help_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                     ..default()
                 },
                 text: Text::with_section(
-                    "Spacebar to jump\nRight arrow to boost",
+                    format!(
+                        "{} to jump\n{} to boost\n{} to duck\nor to fall faster\n{} to pause\nClick or tap to jump\nhold to boost",
+                        key_name(bindings.key(Action::Jump)),
+                        key_name(bindings.key(Action::Boost)),
+                        key_name(bindings.key(Action::Down)),
+                        key_name(bindings.key(Action::Pause)),
+                    ),
                     button_text_style.clone(),
                     Default::default(),
                 ),
@@ -299,7 +574,7 @@ fn help_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                     ..default()
                 },
                 text: Text::with_section(
-                    "Jump over the obstacles and gain points based on the distance. Note that the score starts from negative as each level starts with a buffer area with no obstacles generated.",
+                    "Jump over the obstacles, or land on top of them, and gain points based on the distance. Under a ceiling, gravity flips and you run upside down. Boosting doubles the points for the distance. Jumping narrowly over obstacles, and landing jumps in a row without boosting, earn bonus points.",
                     TextStyle {
                         font: asset_server.load("fonts/undefined-medium.ttf"),
                         font_size: 20.0,
@@ -326,6 +601,341 @@ fn help_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         });
 }
 
+fn statistics_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    statistics: Res<Statistics>,
+) {
+    let font = asset_server.load("fonts/undefined-medium.ttf");
+    let button_style = Style {
+        size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
+        margin: Rect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font: font.clone(),
+        font_size: 40.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: Rect::all(Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::ORANGE.into(),
+            ..default()
+        })
+        .insert(OnStatisticsMenuScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                style: Style {
+                    margin: Rect::all(Val::Px(TEXT_MARGIN)),
+                    ..default()
+                },
+                text: Text::with_section(
+                    "Statistics",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 0.75 * HEADING_REM,
+                        color: Color::WHITE,
+                    },
+                    Default::default(),
+                ),
+                ..default()
+            });
+            parent.spawn_bundle(TextBundle {
+                style: Style {
+                    margin: Rect::all(Val::Px(TEXT_MARGIN)),
+                    ..default()
+                },
+                text: Text::with_section(
+                    statistics.summary(),
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 28.0,
+                        color: Color::WHITE,
+                    },
+                    Default::default(),
+                ),
+                ..default()
+            });
+            // Display the back button to return to the main menu screen
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: button_style,
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::BackToMainMenu)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::with_section("Back", button_text_style, Default::default()),
+                        ..default()
+                    });
+                });
+        });
+}
+
+fn controls_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    *rebinding = Rebinding::default();
+
+    // smaller buttons than the other screens, to fit one per action
+    let button_style = Style {
+        size: Size::new(Val::Px(2.0 * BUTTON_WIDTH), Val::Px(0.6 * BUTTON_HEIGHT)),
+        margin: Rect::all(Val::Px(4.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font: asset_server.load("fonts/undefined-medium.ttf"),
+        font_size: 28.0,
+        color: Color::WHITE,
+    };
+    let small_text_style = TextStyle {
+        font: asset_server.load("fonts/undefined-medium.ttf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                margin: Rect::all(Val::Auto),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::ORANGE.into(),
+            ..default()
+        })
+        .insert(OnControlsMenuScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                style: Style {
+                    margin: Rect::all(Val::Px(TEXT_MARGIN)),
+                    ..default()
+                },
+                text: Text::with_section(
+                    "Click an action, then press its new key or button",
+                    small_text_style.clone(),
+                    Default::default(),
+                ),
+                ..default()
+            });
+            // One button per action, its text is kept up to date by `binding_text_system`
+            for action in Action::ALL {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: button_style.clone(),
+                        color: NORMAL_BUTTON.into(),
+                        ..default()
+                    })
+                    .insert(MenuButtonAction::Rebind(action))
+                    .with_children(|parent| {
+                        parent
+                            .spawn_bundle(TextBundle {
+                                text: Text::with_section(
+                                    "",
+                                    button_text_style.clone(),
+                                    Default::default(),
+                                ),
+                                ..default()
+                            })
+                            .insert(BindingText(action));
+                    });
+            }
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(TEXT_MARGIN)),
+                        ..default()
+                    },
+                    text: Text::with_section("", small_text_style, Default::default()),
+                    ..default()
+                })
+                .insert(RebindStatusText);
+            // Display the back button to return to the main menu screen
+            parent
+                .spawn_bundle(ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
+                        ..button_style
+                    },
+                    color: NORMAL_BUTTON.into(),
+                    ..default()
+                })
+                .insert(MenuButtonAction::BackToMainMenu)
+                .with_children(|parent| {
+                    parent.spawn_bundle(TextBundle {
+                        text: Text::with_section("Back", button_text_style, Default::default()),
+                        ..default()
+                    });
+                });
+        });
+}
+
+// Edits the name of a new high score: typed on a keyboard, or spelled on a gamepad with the
+// d-pad cycling through the letters of the last character
+fn name_entry_system(
+    mut characters: EventReader<ReceivedCharacter>,
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    active_gamepad: Res<ActiveGamepad>,
+    mut name_entry: ResMut<NameEntry>,
+    mut high_scores: ResMut<HighScores>,
+) {
+    if name_entry.index.is_none() {
+        return;
+    }
+    let name = &mut name_entry.name;
+    for event in characters.iter() {
+        let character = event.char;
+        // a name starts with a letter or a digit, which also leaves out the space of a last jump
+        let allowed =
+            character.is_alphanumeric() || (!name.is_empty() && " -_.".contains(character));
+        if allowed && name.chars().count() < NAME_LENGTH {
+            name.push(character);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) {
+        name.pop();
+    }
+    let mut confirmed = keyboard_input.just_pressed(KeyCode::Return);
+
+    if let Some(gamepad) = active_gamepad.0 {
+        let pressed = |button| gamepad_buttons.just_pressed(GamepadButton(gamepad, button));
+        let step = if pressed(GamepadButtonType::DPadUp) {
+            1
+        } else if pressed(GamepadButtonType::DPadDown) {
+            NAME_CHARACTERS.len() - 1
+        } else {
+            0
+        };
+        if step > 0 {
+            let last = name.pop().unwrap_or(' ');
+            let index = NAME_CHARACTERS
+                .find(last)
+                .unwrap_or(NAME_CHARACTERS.len() - 1);
+            let next = (index + step) % NAME_CHARACTERS.len();
+            name.push(NAME_CHARACTERS.as_bytes()[next] as char);
+        }
+        if (pressed(GamepadButtonType::DPadRight) || pressed(GamepadButtonType::South))
+            && name.chars().count() < NAME_LENGTH
+        {
+            name.push('A');
+        }
+        if pressed(GamepadButtonType::DPadLeft) || pressed(GamepadButtonType::East) {
+            name.pop();
+        }
+        confirmed |= pressed(GamepadButtonType::Start);
+    }
+
+    if confirmed {
+        name_entry.commit(&mut high_scores);
+    }
+}
+
+fn name_entry_text_system(
+    name_entry: Res<NameEntry>,
+    mut query: Query<&mut Text, With<NameEntryText>>,
+) {
+    let value = match name_entry.index {
+        Some(_) => format!("New high score! Name: {}_", name_entry.name),
+        None if name_entry.name.trim().is_empty() => "New high score!".to_string(),
+        None => format!("New high score by {}", name_entry.name.trim()),
+    };
+    for mut text in query.iter_mut() {
+        text.sections[0].value = value.clone();
+    }
+}
+
+// Leaving the game over screen keeps the name entered so far
+fn name_entry_exit(mut name_entry: ResMut<NameEntry>, mut high_scores: ResMut<HighScores>) {
+    name_entry.commit(&mut high_scores);
+}
+
+// Binds the first key or gamepad button pressed to the action waiting for a new one
+fn rebind_capture_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    active_gamepad: Res<ActiveGamepad>,
+    mut bindings: ResMut<InputBindings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let action = match rebinding.action {
+        Some(val) => val,
+        None => return,
+    };
+    let (result, name) = if let Some(&key) = keyboard_input.get_just_pressed().next() {
+        (bindings.rebind(action, key), format!("{:?}", key))
+    } else if let Some(&GamepadButton(_, button)) = gamepad_buttons
+        .get_just_pressed()
+        .find(|GamepadButton(gamepad, _)| Some(*gamepad) == active_gamepad.0)
+    {
+        (
+            bindings.rebind_button(action, button),
+            format!("{:?}", button),
+        )
+    } else {
+        return;
+    };
+    rebinding.action = None;
+    rebinding.status = match result {
+        Ok(()) => {
+            bindings.save();
+            format!("{} is now bound to {}", action.name(), name)
+        }
+        Err(other) => format!(
+            "{} is already bound to {}, rebind that first",
+            name,
+            other.name()
+        ),
+    };
+}
+
+fn binding_text_system(
+    bindings: Res<InputBindings>,
+    rebinding: Res<Rebinding>,
+    mut binding_query: Query<(&mut Text, &BindingText), Without<RebindStatusText>>,
+    mut status_query: Query<&mut Text, With<RebindStatusText>>,
+) {
+    if !bindings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (mut text, BindingText(action)) in binding_query.iter_mut() {
+        text.sections[0].value = if rebinding.action == Some(*action) {
+            format!("{}: ...", action.name())
+        } else {
+            format!(
+                "{}: {} / {}",
+                action.name(),
+                key_name(bindings.key(*action)),
+                button_name(bindings.button(*action))
+            )
+        };
+    }
+    for mut text in status_query.iter_mut() {
+        text.sections[0].value = match rebinding.action {
+            Some(action) => format!(
+                "Press a key or button for {}, or click it again to cancel",
+                action.name()
+            ),
+            None => rebinding.status.clone(),
+        };
+    }
+}
+
 fn load_game_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     let button_style = Style {
         size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
@@ -399,7 +1009,15 @@ fn load_game_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>)
         });
 }
 
-fn game_over_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn game_over_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    score: Option<Res<Score>>,
+    seed_state: Res<State<SeedState>>,
+    high_scores: Res<HighScores>,
+    name_entry: Res<NameEntry>,
+) {
+    let seed = seed_state.current().value;
     let button_style = Style {
         size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
         margin: Rect::all(Val::Px(20.0)),
@@ -432,12 +1050,81 @@ fn game_over_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>)
                     ..default()
                 },
                 text: Text::with_section(
-                    "Game over!",
+                    format!(
+                        "Game over!\nScore: {}",
+                        score.map_or(0, |score| score.total())
+                    ),
                     button_text_style.clone(),
                     Default::default(),
                 ),
                 ..default()
             });
+            // Display the name entry when the run made it into the high scores
+            if name_entry.index.is_some() {
+                parent
+                    .spawn_bundle(TextBundle {
+                        text: Text::with_section(
+                            "",
+                            TextStyle {
+                                font: asset_server.load("fonts/undefined-medium.ttf"),
+                                font_size: 30.0,
+                                color: Color::WHITE,
+                            },
+                            Default::default(),
+                        ),
+                        ..default()
+                    })
+                    .insert(NameEntryText);
+                parent.spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "Type your name and press Enter, \
+                         or spell it with the d-pad and press Start",
+                        TextStyle {
+                            font: asset_server.load("fonts/undefined-medium.ttf"),
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                        },
+                        Default::default(),
+                    ),
+                    ..default()
+                });
+            }
+            // Display the best runs of this level and of all levels side by side
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::FlexStart,
+                        ..default()
+                    },
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    let tables = [("This level", Some(seed)), ("All levels", None)];
+                    for (title, seed) in tables {
+                        parent.spawn_bundle(TextBundle {
+                            style: Style {
+                                margin: Rect::all(Val::Px(10.0)),
+                                ..default()
+                            },
+                            text: Text::with_section(
+                                format!(
+                                    "{}\n{}",
+                                    title,
+                                    table_text(high_scores.top(seed, Mode::Normal))
+                                ),
+                                TextStyle {
+                                    font: asset_server.load("fonts/undefined-medium.ttf"),
+                                    font_size: 20.0,
+                                    color: Color::WHITE,
+                                },
+                                Default::default(),
+                            ),
+                            ..default()
+                        });
+                    }
+                });
             // Display the back button to return to the main menu screen
             parent
                 .spawn_bundle(ButtonBundle {
@@ -460,6 +1147,7 @@ fn game_over_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>)
 }
 
 fn menu_action(
+    mut commands: Commands,
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
         (Changed<Interaction>, With<Button>),
@@ -468,31 +1156,59 @@ fn menu_action(
     mut menu_state: ResMut<State<MenuState>>,
     mut game_state: ResMut<State<GameState>>,
     mut seed_state: ResMut<State<SeedState>>,
+    mut rebinding: ResMut<Rebinding>,
+    active_gamepad: Res<ActiveGamepad>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    focus_query: Query<&MenuButtonAction, With<GamepadFocus>>,
 ) {
-    for (interaction, menu_button_action) in interaction_query.iter() {
-        if *interaction == Interaction::Clicked {
-            match menu_button_action {
-                MenuButtonAction::Quit => app_exit_events.send(AppExit),
-                MenuButtonAction::NewGame => {
-                    game_state.set(GameState::Game).unwrap();
-                    menu_state.set(MenuState::Disabled).unwrap();
-                }
-                MenuButtonAction::LoadMenu => {
-                    menu_state.set(MenuState::LoadMenu).unwrap();
-                    // ignore error from setting state again
-                    let _ = seed_state.set(SeedState {
-                        value: FIXED_RNG_SEED,
-                    });
-                }
-                MenuButtonAction::Help => menu_state.set(MenuState::Help).unwrap(),
-                MenuButtonAction::BackToMainMenu => menu_state.set(MenuState::MainMenu).unwrap(),
-                MenuButtonAction::Thanks => {
-                    game_state.set(GameState::MainMenu).unwrap();
-                    menu_state.set(MenuState::MainMenu).unwrap();
-                    let mut rng = ThreadRng::default();
-                    let seed: u64 = rng.gen();
-                    seed_state.set(SeedState { value: seed }).unwrap();
-                }
+    let clicked = interaction_query
+        .iter()
+        .filter(|(interaction, _)| **interaction == Interaction::Clicked)
+        .map(|(_, menu_button_action)| menu_button_action);
+    // the south button confirms the button focused with the gamepad
+    let confirmed = active_gamepad
+        .0
+        .filter(|&gamepad| {
+            gamepad_buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::South))
+        })
+        .and_then(|_| focus_query.iter().next());
+    for menu_button_action in clicked.chain(confirmed) {
+        match menu_button_action {
+            MenuButtonAction::Quit => app_exit_events.send(AppExit),
+            MenuButtonAction::NewGame => {
+                game_state.set(GameState::Game).unwrap();
+                menu_state.set(MenuState::Disabled).unwrap();
+            }
+            MenuButtonAction::Practice => {
+                commands.insert_resource(Practice);
+                game_state.set(GameState::Game).unwrap();
+                menu_state.set(MenuState::Disabled).unwrap();
+            }
+            MenuButtonAction::LoadMenu => {
+                menu_state.set(MenuState::LoadMenu).unwrap();
+                // ignore error from setting state again
+                let _ = seed_state.set(SeedState {
+                    value: FIXED_RNG_SEED,
+                });
+            }
+            MenuButtonAction::Help => menu_state.set(MenuState::Help).unwrap(),
+            MenuButtonAction::Statistics => menu_state.set(MenuState::Statistics).unwrap(),
+            MenuButtonAction::Controls => menu_state.set(MenuState::Controls).unwrap(),
+            MenuButtonAction::Rebind(action) => {
+                // clicking the action waiting for a key again cancels the rebind
+                rebinding.action = if rebinding.action == Some(*action) {
+                    None
+                } else {
+                    Some(*action)
+                };
+            }
+            MenuButtonAction::BackToMainMenu => menu_state.set(MenuState::MainMenu).unwrap(),
+            MenuButtonAction::Thanks => {
+                game_state.set(GameState::MainMenu).unwrap();
+                menu_state.set(MenuState::MainMenu).unwrap();
+                let mut rng = ThreadRng::default();
+                let seed: u64 = rng.gen();
+                seed_state.set(SeedState { value: seed }).unwrap();
             }
         }
     }
@@ -0,0 +1,943 @@
+use bevy::prelude::*;
+
+use crate::collision::CollisionShape;
+use crate::level::{in_gravity_zone, Level, CEILING_HEIGHT, SPAWN_X};
+use crate::replay::TickInput;
+use crate::tuning::Tuning;
+
+/// Height of the ground the player rolls on when not supported by anything else
+pub const GROUND_HEIGHT: f32 = 0.0;
+
+/// Radius of the boost energy pickups
+pub const PICKUP_RADIUS: f32 = 0.2;
+
+/// Distance from the camera to the plane the player moves on
+pub const CAMERA_DISTANCE: f32 = 8.0;
+
+/// Points for every unit of distance from the spawn point
+pub const POINTS_PER_DISTANCE: f32 = 10.0;
+
+/// Multiplier of the distance points while boosting
+pub const BOOST_MULTIPLIER: f32 = 2.0;
+
+/// Largest gap between the player and an obstacle passed in the air that counts as a near miss
+pub const NEAR_MISS_MARGIN: f32 = 0.3;
+
+/// Bonus points for a near miss
+pub const NEAR_MISS_POINTS: u32 = 50;
+
+/// Bonus points for a clean jump, multiplied by the number of clean jumps in a row
+pub const CLEAN_JUMP_POINTS: u32 = 10;
+
+/// Parts of the simulation state covered by `state_hash`, one byte of the hash each
+pub const HASHED_FIELDS: [&str; 8] = [
+    "player x",
+    "player y",
+    "player velocity x",
+    "player velocity y",
+    "player jump state",
+    "camera x",
+    "camera y",
+    "camera z",
+];
+
+#[derive(Component, Clone)]
+pub(crate) struct Player {
+    pub jumping: JumpState,
+    /// Set by the hit ending the run, the player stops moving
+    pub collided: bool,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    /// Boosting on this tick, the boost was held with energy left
+    pub boosting: bool,
+    /// Took off from the floor on this tick
+    pub jumped: bool,
+    /// Height of the surface currently below the player, either the ground or an obstacle top
+    pub floor_height: f32,
+    /// Backwards velocity left over from the last hit
+    pub knockback_x: f32,
+    /// Ducking on the floor, with a smaller collision shape
+    pub crouching: bool,
+    /// Which way gravity pulls the player
+    pub orientation: Orientation,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            jumping: JumpState::OnFloor,
+            velocity_x: 0.0,
+            velocity_y: 0.0,
+            boosting: false,
+            jumped: false,
+            collided: false,
+            floor_height: GROUND_HEIGHT,
+            knockback_x: 0.0,
+            crouching: false,
+            orientation: Orientation::Normal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Orientation {
+    /// Gravity pulls down, towards the ground
+    Normal,
+    /// Gravity pulls up, towards the ceiling
+    Flipped,
+}
+
+impl Orientation {
+    /// Unit vector along the y axis pointing away from the floor
+    pub fn up(self) -> f32 {
+        match self {
+            Orientation::Normal => 1.0,
+            Orientation::Flipped => -1.0,
+        }
+    }
+
+    /// Height of the floor when no obstacle supports the player
+    pub fn floor_height(self) -> f32 {
+        match self {
+            Orientation::Normal => GROUND_HEIGHT,
+            Orientation::Flipped => CEILING_HEIGHT,
+        }
+    }
+}
+
+/// Energy spent by boosting, see `Tuning::boost_energy`
+#[derive(Component, Clone)]
+pub(crate) struct BoostEnergy {
+    pub current: f32,
+}
+
+#[derive(Component, Clone)]
+pub(crate) struct Health {
+    pub lives: u32,
+    /// Seconds left before the player can be hit again
+    pub invulnerable_for: f32,
+}
+
+impl Health {
+    pub fn new(lives: u32) -> Self {
+        Self {
+            lives,
+            invulnerable_for: 0.0,
+        }
+    }
+}
+
+/// Bonus points earned on top of the distance
+#[derive(Clone, Copy)]
+pub(crate) enum Bonus {
+    /// Passed an obstacle in the air within `NEAR_MISS_MARGIN` of it
+    NearMiss,
+    /// Landed a jump without boosting, the `combo`th one in a row
+    CleanJump { combo: u32 },
+}
+
+impl Bonus {
+    pub fn points(self) -> u32 {
+        match self {
+            Bonus::NearMiss => NEAR_MISS_POINTS,
+            Bonus::CleanJump { combo } => CLEAN_JUMP_POINTS * combo,
+        }
+    }
+}
+
+/// Points of a run, earned for the distance from the spawn point and for bonuses
+#[derive(Clone, Default)]
+pub(crate) struct Score {
+    /// Furthest distance from the spawn point, only new distance earns points
+    pub distance: f32,
+    /// Points earned for the distance, with the multiplier of the tick it was covered on
+    distance_points: f32,
+    /// Points earned for bonuses
+    pub bonus: u32,
+    /// Clean jumps landed in a row, since the last boost or hit
+    pub combo: u32,
+    /// Whether the jump in progress is still clean, `None` when not jumping
+    clean_jump: Option<bool>,
+}
+
+impl Score {
+    /// Multiplier of the distance points earned on this tick
+    pub fn multiplier(&self, player: &Player) -> f32 {
+        if player.boosting {
+            BOOST_MULTIPLIER
+        } else {
+            1.0
+        }
+    }
+
+    /// Earns the points for the distance the player has gained on this tick, and the bonuses
+    /// of the tick, given whether the player was hit and how many obstacles it nearly missed
+    pub fn tick(
+        &mut self,
+        player: &Player,
+        position: Vec3,
+        hit: bool,
+        near_misses: u32,
+    ) -> Vec<Bonus> {
+        let distance = position.x - SPAWN_X;
+        if distance > self.distance {
+            let gained = distance - self.distance;
+            self.distance_points += gained * POINTS_PER_DISTANCE * self.multiplier(player);
+            self.distance = distance;
+        }
+
+        // a hit breaks the combo, and the knockback that follows is no jump
+        if hit {
+            self.combo = 0;
+            self.clean_jump = None;
+            return Vec::new();
+        }
+        let mut bonuses = vec![Bonus::NearMiss; near_misses as usize];
+        if player.boosting {
+            self.combo = 0;
+            if let Some(clean) = &mut self.clean_jump {
+                *clean = false;
+            }
+        }
+        if player.jumped {
+            self.clean_jump = Some(!player.boosting);
+        } else if player.jumping == JumpState::OnFloor && self.clean_jump.take() == Some(true) {
+            self.combo += 1;
+            bonuses.push(Bonus::CleanJump { combo: self.combo });
+        }
+        self.bonus += bonuses.iter().map(|bonus| bonus.points()).sum::<u32>();
+        bonuses
+    }
+
+    pub fn total(&self) -> u32 {
+        self.distance_points as u32 + self.bonus
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JumpState {
+    OnFloor,
+    InAir,
+}
+
+/// Moves the player by one tick, following the tick's input
+pub(crate) fn move_player(
+    player: &mut Player,
+    energy: &mut BoostEnergy,
+    shape: &mut CollisionShape,
+    translation: &mut Vec3,
+    input: TickInput,
+    tuning: &Tuning,
+    gravity_zones: &[(f32, f32)],
+) {
+    if player.collided {
+        return;
+    }
+
+    let speed_curve = &tuning.speed_curve;
+    let time_step = tuning.time_step;
+    let distance = translation.x - SPAWN_X;
+    // Jumps get faster as the scroll speed ramps up, while staying as high and as long
+    let speed_factor = speed_curve.speed_factor(distance);
+    let jump_initial_velocity = tuning.jump_initial_velocity * speed_factor;
+    let mut gravity = tuning.gravity * speed_factor * speed_factor;
+
+    // x direction, boosting for as long as there is energy left. Starting a boost takes a
+    // minimum of energy, or holding boost on an empty meter would alternate between recharging
+    // and boosting every tick.
+    let energy_settings = &tuning.boost_energy;
+    let min_energy = if player.boosting {
+        0.0
+    } else {
+        energy_settings.min_to_start
+    };
+    player.boosting = input.boost && energy.current > min_energy;
+    if player.boosting {
+        player.velocity_x = speed_curve.boost_velocity(distance);
+        energy.current = (energy.current - energy_settings.drain * time_step).max(0.0);
+    } else {
+        player.velocity_x = speed_curve.scroll_velocity(distance);
+        energy.current =
+            (energy.current + energy_settings.recharge * time_step).min(energy_settings.max);
+    }
+
+    translation.x += (player.velocity_x - player.knockback_x) * time_step;
+    player.knockback_x = (player.knockback_x - tuning.knockback_recovery * time_step).max(0.0);
+
+    // gravity flips inside gravity zones, and the player falls towards the new floor
+    let orientation = if in_gravity_zone(gravity_zones, translation.x) {
+        Orientation::Flipped
+    } else {
+        Orientation::Normal
+    };
+    if orientation != player.orientation {
+        player.orientation = orientation;
+        player.jumping = JumpState::InAir;
+        player.floor_height = orientation.floor_height();
+    }
+    // heights and vertical velocities below are measured along `up`
+    let up = player.orientation.up();
+
+    // y direction
+    player.jumped = false;
+    if input.jump {
+        match &player.jumping {
+            JumpState::OnFloor => {
+                player.jumped = true;
+                player.jumping = JumpState::InAir;
+                player.velocity_y = up * jump_initial_velocity;
+            }
+            JumpState::InAir => {}
+        }
+    }
+
+    // down ducks on the floor, and falls faster in the air
+    player.crouching = false;
+    if input.down {
+        match player.jumping {
+            JumpState::OnFloor => player.crouching = true,
+            JumpState::InAir => gravity *= tuning.fast_fall_gravity,
+        }
+    }
+
+    // the collision shape shrinks while ducking, keeping its bottom in place
+    let radius = if player.crouching {
+        tuning.sphere_radius * tuning.crouch_scale
+    } else {
+        tuning.sphere_radius
+    };
+    *shape = CollisionShape {
+        radius,
+        offset_y: up * (radius - tuning.sphere_radius),
+    };
+
+    // rolled off the edge of the supporting surface
+    let height = up * (translation.y - player.floor_height);
+    if let JumpState::OnFloor = player.jumping {
+        if height > 0.0 {
+            player.jumping = JumpState::InAir;
+        }
+    }
+
+    // floor min height
+    if height < 0.0 {
+        player.jumping = JumpState::OnFloor;
+        player.velocity_y = 0.0;
+        translation.y = player.floor_height;
+    }
+
+    player.velocity_y -= up * gravity * time_step;
+    let velocity = player.velocity_y;
+
+    // dbg!(&translation.y);
+    match player.jumping {
+        JumpState::OnFloor => {
+            player.velocity_y = 0.0;
+            translation.y = player.floor_height;
+        }
+        JumpState::InAir => translation.y += velocity * time_step,
+    }
+}
+
+/// Counts down the time left before the player can be hit again
+pub(crate) fn tick_invulnerability(health: &mut Health, tuning: &Tuning) {
+    health.invulnerable_for = (health.invulnerable_for - tuning.time_step).max(0.0);
+}
+
+/// Where the camera starts, ahead of the player at the spawn point
+pub(crate) fn camera_start(tuning: &Tuning) -> Vec3 {
+    Vec3::new(SPAWN_X + tuning.camera.offset_x, 0.0, CAMERA_DISTANCE)
+}
+
+/// Moves the camera by one tick towards where it should be to follow the player
+pub(crate) fn follow_player(camera: Vec3, player: &Player, target: Vec3, tuning: &Tuning) -> Vec3 {
+    let settings = &tuning.camera;
+    let extra_speed = (player.velocity_x - tuning.speed_curve.base).max(0.0);
+    let desired = Vec3::new(
+        target.x + settings.offset_x + settings.look_ahead * extra_speed,
+        target.y,
+        CAMERA_DISTANCE + settings.zoom_out * extra_speed,
+    );
+    let vertical_follow = match player.jumping {
+        JumpState::OnFloor => settings.vertical_follow,
+        JumpState::InAir => settings.vertical_follow_in_air,
+    };
+
+    // exponential smoothing towards the desired position, independent of the time step
+    let approach = |rate: f32| 1.0 - (-rate * tuning.time_step).exp();
+    Vec3::new(
+        camera.x + (desired.x - camera.x) * approach(settings.horizontal_follow),
+        camera.y + (desired.y - camera.y) * approach(vertical_follow),
+        camera.z + (desired.z - camera.z) * approach(settings.horizontal_follow),
+    )
+}
+
+/// Hash of the simulation state at the end of a tick, for detecting desyncs between two
+/// simulations of the same run. Each of the `HASHED_FIELDS` gets its own byte, so that
+/// the first byte differing tells which field diverged.
+pub(crate) fn state_hash(player: &Player, position: Vec3, camera: Vec3) -> u64 {
+    let jump_state = (player.jumping == JumpState::InAir) as u32
+        | ((player.orientation == Orientation::Flipped) as u32) << 1
+        | (player.crouching as u32) << 2
+        | (player.collided as u32) << 3;
+    let fields = [
+        position.x.to_bits(),
+        position.y.to_bits(),
+        player.velocity_x.to_bits(),
+        player.velocity_y.to_bits(),
+        jump_state,
+        camera.x.to_bits(),
+        camera.y.to_bits(),
+        camera.z.to_bits(),
+    ];
+    fields.iter().enumerate().fold(0, |hash, (i, &bits)| {
+        // Fibonacci hashing, keeping the top byte
+        let byte = bits.wrapping_mul(0x9e37_79b9) >> 24;
+        hash | (byte as u64) << (8 * i)
+    })
+}
+
+/// Name of the first field that differs between two state hashes
+pub(crate) fn hash_difference(a: u64, b: u64) -> Option<&'static str> {
+    let diff = a ^ b;
+    if diff == 0 {
+        return None;
+    }
+    Some(HASHED_FIELDS[diff.trailing_zeros() as usize / 8])
+}
+
+/// A collider near the player, keyed on whatever identifies it for the caller
+pub(crate) struct Contact<K> {
+    pub key: K,
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub pickup: bool,
+}
+
+/// What happened to the player while resolving its contacts
+pub(crate) struct ContactOutcome<K> {
+    /// Pickups collected this tick
+    pub picked_up: Vec<K>,
+    /// Collider that cost the player a life
+    pub hit: Option<K>,
+    /// Obstacles passed in the air within `NEAR_MISS_MARGIN`, without a hit
+    pub near_misses: u32,
+}
+
+/// Resolves the contacts of the player with the colliders around it: collects pickups, lands
+/// on obstacle tops, loses a life on any other touch, and counts the near misses. The colliders
+/// are resolved in order of position, so that the outcome does not depend on how they were found.
+pub(crate) fn resolve_contacts<K>(
+    player: &mut Player,
+    health: &mut Health,
+    energy: &mut BoostEnergy,
+    player_shape: &CollisionShape,
+    position: Vec3,
+    mut contacts: Vec<Contact<K>>,
+    tuning: &Tuning,
+) -> ContactOutcome<K> {
+    let mut outcome = ContactOutcome {
+        picked_up: Vec::new(),
+        hit: None,
+        near_misses: 0,
+    };
+    contacts.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+
+    let x1 = position.x;
+    let up = player.orientation.up();
+    // height the player was at before this tick's movement
+    let previous_x1 = position.x - player.velocity_x * tuning.time_step;
+    let previous_y1 = position.y - player.velocity_y * tuning.time_step;
+    // collision tests use the center of the player's shape, which is lower while ducking
+    let y1 = position.y + player_shape.offset_y;
+    let mut floor_height = player.orientation.floor_height();
+    // Simple sphere collision based on center and radius
+    for contact in contacts {
+        let (x2, y2) = (contact.x, contact.y);
+        let distance = ((x2 - x1).powf(2.0) + (y2 - y1).powf(2.0)).sqrt();
+        let reach = player_shape.radius + contact.radius;
+
+        if contact.pickup {
+            if distance <= reach {
+                let settings = &tuning.boost_energy;
+                energy.current = (energy.current + settings.pickup).min(settings.max);
+                outcome.picked_up.push(contact.key);
+            }
+            continue;
+        }
+
+        // A near miss is counted on the tick the player passes over or under the obstacle's
+        // center, which is about where the two are closest
+        let gap = distance - reach;
+        if player.jumping == JumpState::InAir
+            && health.invulnerable_for <= 0.0
+            && previous_x1 < x2
+            && x2 <= x1
+            && gap > 0.0
+            && gap <= NEAR_MISS_MARGIN
+        {
+            outcome.near_misses += 1;
+        }
+
+        // The top of an obstacle supports the player if the player is above its footprint,
+        // and was above its top before this tick. Landing there is not a hit.
+        // With flipped gravity, "above" and "top" are measured downwards.
+        let top = y2 + up * reach - player_shape.offset_y;
+        if (x2 - x1).abs() < contact.radius && up * (previous_y1 - top) >= 0.0 {
+            if up * (top - floor_height) > 0.0 {
+                floor_height = top;
+            }
+            continue;
+        }
+
+        if distance <= reach && health.invulnerable_for <= 0.0 {
+            outcome.hit = Some(contact.key);
+            outcome.near_misses = 0;
+            health.lives = health.lives.saturating_sub(1);
+
+            if health.lives == 0 {
+                player.collided = true;
+                break;
+            }
+
+            // survived the hit: bounce back and become briefly invulnerable
+            health.invulnerable_for = tuning.invulnerability_time;
+            player.knockback_x = tuning.knockback_velocity_x;
+            player.jumping = JumpState::InAir;
+            player.velocity_y = up * tuning.knockback_velocity_y;
+            break;
+        }
+    }
+    player.floor_height = floor_height;
+    outcome
+}
+
+/// The colliders of a level, for simulating a player outside of the game world
+pub(crate) struct Course {
+    /// Obstacles and pickups, sorted by x
+    colliders: Vec<(f32, f32, f32, bool)>,
+    gravity_zones: Vec<(f32, f32)>,
+}
+
+impl Course {
+    pub fn new(level: &Level, tuning: &Tuning) -> Self {
+        let obstacles = level
+            .obstacles
+            .iter()
+            .map(|obstacle| (obstacle.x, obstacle.y, tuning.sphere_radius, false));
+        let pickups = level
+            .pickups
+            .iter()
+            .map(|&(x, y)| (x, y, PICKUP_RADIUS, true));
+        let mut colliders: Vec<_> = obstacles.chain(pickups).collect();
+        colliders.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            colliders,
+            gravity_zones: level.gravity_zones.clone(),
+        }
+    }
+}
+
+/// A player simulated outside of the game world, with the same physics as the player entity
+#[derive(Clone)]
+pub(crate) struct Runner {
+    pub player: Player,
+    pub health: Health,
+    pub energy: BoostEnergy,
+    pub shape: CollisionShape,
+    pub position: Vec3,
+    pub camera: Vec3,
+    pub score: Score,
+    /// Pickups of the course already collected by this runner
+    collected: Vec<bool>,
+}
+
+impl Runner {
+    pub fn new(course: &Course, tuning: &Tuning) -> Self {
+        Self {
+            player: Player::default(),
+            health: Health::new(tuning.lives),
+            energy: BoostEnergy {
+                current: tuning.boost_energy.max,
+            },
+            shape: CollisionShape::sphere(tuning.sphere_radius),
+            position: Vec3::new(SPAWN_X, 0.0, 0.0),
+            camera: camera_start(tuning),
+            score: Score::default(),
+            collected: vec![false; course.colliders.len()],
+        }
+    }
+
+    /// Simulates one tick, in the same order as the gameplay systems
+    pub fn step(&mut self, input: TickInput, course: &Course, tuning: &Tuning) {
+        let outcome = self.move_and_collide(input, course, tuning);
+        self.score.tick(
+            &self.player,
+            self.position,
+            outcome.hit.is_some(),
+            outcome.near_misses,
+        );
+        self.camera = follow_player(self.camera, &self.player, self.position, tuning);
+    }
+
+    /// State hash at the end of the last tick, see `state_hash`
+    pub fn state_hash(&self) -> u64 {
+        state_hash(&self.player, self.position, self.camera)
+    }
+
+    fn move_and_collide(
+        &mut self,
+        input: TickInput,
+        course: &Course,
+        tuning: &Tuning,
+    ) -> ContactOutcome<usize> {
+        move_player(
+            &mut self.player,
+            &mut self.energy,
+            &mut self.shape,
+            &mut self.position,
+            input,
+            tuning,
+            &course.gravity_zones,
+        );
+        tick_invulnerability(&mut self.health, tuning);
+        if self.player.collided {
+            return ContactOutcome {
+                picked_up: Vec::new(),
+                hit: None,
+                near_misses: 0,
+            };
+        }
+
+        let reach = self.shape.radius + tuning.sphere_radius;
+        let start = course
+            .colliders
+            .partition_point(|&(x, ..)| x < self.position.x - reach);
+        let contacts = course.colliders[start..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, &(x, ..))| x <= self.position.x + reach)
+            .map(|(i, &(x, y, radius, pickup))| Contact {
+                key: start + i,
+                x,
+                y,
+                radius,
+                pickup,
+            })
+            .filter(|contact| !self.collected[contact.key])
+            .collect();
+        let outcome = resolve_contacts(
+            &mut self.player,
+            &mut self.health,
+            &mut self.energy,
+            &self.shape,
+            self.position,
+            contacts,
+            tuning,
+        );
+        for &key in &outcome.picked_up {
+            self.collected[key] = true;
+        }
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_state_hashes_the_same() {
+        let player = Player::default();
+        let position = Vec3::new(SPAWN_X, 0.0, 0.0);
+        let camera = Vec3::new(0.0, 1.0, CAMERA_DISTANCE);
+        let hash = state_hash(&player, position, camera);
+        assert_eq!(hash, state_hash(&player.clone(), position, camera));
+        assert_eq!(hash_difference(hash, hash), None);
+    }
+
+    #[test]
+    fn hash_difference_names_each_field() {
+        let player = Player::default();
+        let position = Vec3::new(SPAWN_X, 0.0, 0.0);
+        let camera = Vec3::new(0.0, 1.0, CAMERA_DISTANCE);
+        let hash = state_hash(&player, position, camera);
+
+        let mut moving = player.clone();
+        moving.velocity_x = 3.0;
+        let mut falling = player.clone();
+        falling.velocity_y = -1.0;
+        let mut jumping = player.clone();
+        jumping.jumping = JumpState::InAir;
+        // one change per field, in the order of `HASHED_FIELDS`
+        let changed = [
+            state_hash(&player, position + Vec3::X, camera),
+            state_hash(&player, position + Vec3::Y, camera),
+            state_hash(&moving, position, camera),
+            state_hash(&falling, position, camera),
+            state_hash(&jumping, position, camera),
+            state_hash(&player, position, camera + Vec3::X),
+            state_hash(&player, position, camera + Vec3::Y),
+            state_hash(&player, position, camera + Vec3::Z),
+        ];
+
+        assert_eq!(changed.len(), HASHED_FIELDS.len());
+        for (field, other) in HASHED_FIELDS.iter().zip(changed) {
+            assert_eq!(hash_difference(hash, other), Some(*field));
+        }
+    }
+
+    // The player's collision shape, standing
+    fn shape(tuning: &Tuning) -> CollisionShape {
+        CollisionShape {
+            radius: tuning.sphere_radius,
+            offset_y: 0.0,
+        }
+    }
+
+    fn obstacle(x: f32, y: f32, tuning: &Tuning) -> Contact<usize> {
+        Contact {
+            key: 0,
+            x,
+            y,
+            radius: tuning.sphere_radius,
+            pickup: false,
+        }
+    }
+
+    // Resolves the contacts of a player with full health and energy at the position
+    fn resolve(
+        player: &mut Player,
+        health: &mut Health,
+        position: Vec3,
+        contacts: Vec<Contact<usize>>,
+        tuning: &Tuning,
+    ) -> ContactOutcome<usize> {
+        let mut energy = BoostEnergy {
+            current: tuning.boost_energy.max,
+        };
+        let shape = shape(tuning);
+        resolve_contacts(
+            player,
+            health,
+            &mut energy,
+            &shape,
+            position,
+            contacts,
+            tuning,
+        )
+    }
+
+    // Scores a tick of the player at the distance from the spawn point, without hits or near misses
+    fn score_tick(score: &mut Score, player: &Player, distance: f32) -> Vec<Bonus> {
+        score.tick(player, Vec3::new(SPAWN_X + distance, 0.0, 0.0), false, 0)
+    }
+
+    // Scores a jump from takeoff to landing, boosting in the air if asked to
+    fn jump(score: &mut Score, boost_in_air: bool) -> Vec<Bonus> {
+        let distance = score.distance;
+        let takeoff = Player {
+            jumped: true,
+            jumping: JumpState::InAir,
+            ..Player::default()
+        };
+        let in_air = Player {
+            jumping: JumpState::InAir,
+            boosting: boost_in_air,
+            ..Player::default()
+        };
+        assert!(score_tick(score, &takeoff, distance).is_empty());
+        assert!(score_tick(score, &in_air, distance).is_empty());
+        score_tick(score, &Player::default(), distance)
+    }
+
+    #[test]
+    fn clean_jumps_build_a_combo() {
+        let mut score = Score::default();
+        for combo in 1..=3 {
+            let bonuses = jump(&mut score, false);
+            assert!(matches!(bonuses[..], [Bonus::CleanJump { combo: c }] if c == combo));
+        }
+        assert_eq!(score.combo, 3);
+        assert_eq!(score.total(), CLEAN_JUMP_POINTS * (1 + 2 + 3));
+    }
+
+    #[test]
+    fn boosting_in_the_air_voids_the_jump() {
+        let mut score = Score::default();
+        jump(&mut score, false);
+        assert!(jump(&mut score, true).is_empty());
+        assert_eq!(score.combo, 0);
+        // the next clean jump starts the combo over
+        assert!(matches!(
+            jump(&mut score, false)[..],
+            [Bonus::CleanJump { combo: 1 }]
+        ));
+    }
+
+    #[test]
+    fn hit_resets_the_combo() {
+        let mut score = Score::default();
+        jump(&mut score, false);
+        let knocked_back = Player {
+            jumping: JumpState::InAir,
+            ..Player::default()
+        };
+        let bonuses = score.tick(&knocked_back, Vec3::new(SPAWN_X, 0.0, 0.0), true, 1);
+        assert!(bonuses.is_empty());
+        assert_eq!(score.combo, 0);
+        // landing from the knockback is no clean jump
+        assert!(score_tick(&mut score, &Player::default(), 0.0).is_empty());
+    }
+
+    #[test]
+    fn boosting_multiplies_the_distance_points() {
+        let mut score = Score::default();
+        score_tick(&mut score, &Player::default(), 1.0);
+        assert_eq!(score.total(), POINTS_PER_DISTANCE as u32);
+        let boosting = Player {
+            boosting: true,
+            ..Player::default()
+        };
+        score_tick(&mut score, &boosting, 2.0);
+        assert_eq!(
+            score.total(),
+            (POINTS_PER_DISTANCE * (1.0 + BOOST_MULTIPLIER)) as u32
+        );
+        // knocked back behind the furthest distance, nothing more is earned
+        score_tick(&mut score, &Player::default(), 1.5);
+        score_tick(&mut score, &Player::default(), 2.0);
+        assert_eq!(score.distance, 2.0);
+        assert_eq!(
+            score.total(),
+            (POINTS_PER_DISTANCE * (1.0 + BOOST_MULTIPLIER)) as u32
+        );
+    }
+
+    #[test]
+    fn near_miss_is_counted_once_per_obstacle() {
+        let tuning = Tuning::default();
+        let reach = 2.0 * tuning.sphere_radius;
+        let mut player = Player {
+            jumping: JumpState::InAir,
+            velocity_x: 5.0,
+            ..Player::default()
+        };
+        let mut health = Health::new(tuning.lives);
+        // passing under a ceiling obstacle, just clear of it
+        let (x2, y2) = (0.0, 3.0);
+        let y = y2 - reach - NEAR_MISS_MARGIN / 2.0;
+        let step = player.velocity_x * tuning.time_step;
+        let mut near_misses = 0;
+        for i in -3..3 {
+            let position = Vec3::new(x2 + i as f32 * step + step / 2.0, y, 0.0);
+            let contacts = vec![obstacle(x2, y2, &tuning)];
+            let outcome = resolve(&mut player, &mut health, position, contacts, &tuning);
+            assert!(outcome.hit.is_none());
+            near_misses += outcome.near_misses;
+        }
+        assert_eq!(near_misses, 1);
+
+        // no near miss on the floor, nor further away than the margin
+        for (jumping, y) in [
+            (JumpState::OnFloor, y),
+            (JumpState::InAir, y - 2.0 * NEAR_MISS_MARGIN),
+        ] {
+            player.jumping = jumping;
+            let position = Vec3::new(x2 + step / 2.0, y, 0.0);
+            let contacts = vec![obstacle(x2, y2, &tuning)];
+            let outcome = resolve(&mut player, &mut health, position, contacts, &tuning);
+            assert_eq!(outcome.near_misses, 0);
+        }
+    }
+
+    #[test]
+    fn near_miss_earns_its_points() {
+        let mut score = Score::default();
+        let bonuses = score.tick(&Player::default(), Vec3::new(SPAWN_X, 0.0, 0.0), false, 2);
+        assert_eq!(bonuses.len(), 2);
+        assert_eq!(score.total(), 2 * NEAR_MISS_POINTS);
+    }
+
+    // Height measured from the floor of the orientation, so that flipped cases mirror normal ones
+    fn height(orientation: Orientation, y: f32) -> f32 {
+        orientation.floor_height() + orientation.up() * y
+    }
+
+    #[test]
+    fn landing_from_above_is_no_hit() {
+        let tuning = Tuning::default();
+        let reach = 2.0 * tuning.sphere_radius;
+        for orientation in [Orientation::Normal, Orientation::Flipped] {
+            let up = orientation.up();
+            let mut player = Player {
+                jumping: JumpState::InAir,
+                velocity_y: -up * 3.0,
+                orientation,
+                ..Player::default()
+            };
+            let mut health = Health::new(tuning.lives);
+            // an obstacle on the floor, with the player sinking into its top during the tick
+            let y2 = height(orientation, tuning.sphere_radius);
+            let top = y2 + up * reach;
+            let position = Vec3::new(0.0, top - up * 0.02, 0.0);
+            let contacts = vec![obstacle(0.0, y2, &tuning)];
+            let outcome = resolve(&mut player, &mut health, position, contacts, &tuning);
+            assert!(outcome.hit.is_none());
+            assert_eq!(health.lives, tuning.lives);
+            assert_eq!(player.floor_height, top);
+        }
+    }
+
+    #[test]
+    fn running_into_a_side_costs_a_life() {
+        let tuning = Tuning::default();
+        let reach = 2.0 * tuning.sphere_radius;
+        for orientation in [Orientation::Normal, Orientation::Flipped] {
+            let mut player = Player {
+                velocity_x: 5.0,
+                orientation,
+                ..Player::default()
+            };
+            let mut health = Health::new(tuning.lives);
+            // on the floor at the height of the obstacle, touching its side
+            let y2 = height(orientation, tuning.sphere_radius);
+            let position = Vec3::new(-0.9 * reach, y2, 0.0);
+            let contacts = vec![obstacle(0.0, y2, &tuning)];
+            let outcome = resolve(&mut player, &mut health, position, contacts, &tuning);
+            assert_eq!(outcome.hit, Some(0));
+            assert_eq!(health.lives, tuning.lives - 1);
+            assert_eq!(player.floor_height, orientation.floor_height());
+            // knocked back, away from the floor
+            assert_eq!(player.jumping, JumpState::InAir);
+            assert_eq!(
+                player.velocity_y,
+                orientation.up() * tuning.knockback_velocity_y
+            );
+        }
+    }
+
+    #[test]
+    fn rising_into_a_bottom_costs_a_life() {
+        let tuning = Tuning::default();
+        let reach = 2.0 * tuning.sphere_radius;
+        for orientation in [Orientation::Normal, Orientation::Flipped] {
+            let up = orientation.up();
+            let mut player = Player {
+                jumping: JumpState::InAir,
+                velocity_y: up * 3.0,
+                orientation,
+                ..Player::default()
+            };
+            let mut health = Health::new(tuning.lives);
+            // an obstacle hanging from the far side, hit from below its footprint
+            let y2 = height(orientation, CEILING_HEIGHT - tuning.sphere_radius);
+            let position = Vec3::new(0.0, y2 - up * 0.9 * reach, 0.0);
+            let contacts = vec![obstacle(0.0, y2, &tuning)];
+            let outcome = resolve(&mut player, &mut health, position, contacts, &tuning);
+            assert_eq!(outcome.hit, Some(0));
+            assert_eq!(health.lives, tuning.lives - 1);
+        }
+    }
+}
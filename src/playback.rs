@@ -0,0 +1,239 @@
+use bevy::prelude::*;
+
+use crate::clock::SimClock;
+use crate::game::{game_setup, GameSystem, OnGameScreen, Recording, SimPosition, REM};
+use crate::input::{key_name, Action, InputBindings};
+use crate::level::SPAWN_X;
+use crate::physics::{Player, Score};
+use crate::replay::{Playback, SCORE_VERSION};
+use crate::tuning::{Tuning, TuningOverride};
+
+use super::GameState;
+
+/// Range of the playback speeds, each speed key doubles or halves it
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+
+/// Seconds of the run skipped by the seek keys
+const SEEK_SECONDS: f32 = 5.0;
+
+/// Ticks simulated per frame while seeking, keeping the game responsive on long seeks
+const MAX_SEEK_TICKS_PER_FRAME: u32 = 600;
+
+pub struct PlaybackPlugin;
+
+impl Plugin for PlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .add_system_set(
+                SystemSet::on_enter(GameState::Playback)
+                    .with_system(playback_setup.after(game_setup)),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playback)
+                    .with_system(playback_control_system)
+                    .with_system(
+                        playback_seek_system
+                            .after(playback_control_system)
+                            .before(GameSystem::Simulation),
+                    )
+                    .with_system(playback_outcome_system.after(GameSystem::Simulation))
+                    .with_system(playback_text_system.after(playback_outcome_system)),
+            );
+    }
+}
+
+// A unit struct to help identify the playback UI component, since there may be many Text components
+#[derive(Component)]
+struct PlaybackText;
+
+fn playback_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut playback: ResMut<Playback>,
+) {
+    playback.outcome = None;
+
+    // replay position, speed and outcome
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(0.0),
+                    right: Val::Px(0.5 * REM),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/undefined-medium.ttf"),
+                    font_size: REM,
+                    color: Color::WHITE,
+                },
+                TextAlignment {
+                    horizontal: HorizontalAlign::Right,
+                    ..default()
+                },
+            ),
+            ..default()
+        })
+        .insert(OnGameScreen)
+        .insert(PlaybackText);
+}
+
+// Up and down change the speed, left and right seek, and the quit action leaves the playback
+fn playback_control_system(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
+    tuning: Res<Tuning>,
+    recording: Option<Res<Recording>>,
+    mut playback: ResMut<Playback>,
+    mut clock: ResMut<SimClock>,
+    mut tuning_override: ResMut<TuningOverride>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    if actions.just_pressed(Action::Quit) {
+        commands.remove_resource::<Playback>();
+        tuning_override.0 = None;
+        // ignore error from quitting again before the state changed
+        let _ = game_state.set(GameState::MainMenu);
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Up) {
+        clock.time_scale = (clock.time_scale * 2.0).min(MAX_SPEED);
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+        clock.time_scale = (clock.time_scale / 2.0).max(MIN_SPEED);
+    }
+
+    let tick = match (&playback.seek_to, &recording) {
+        (Some(target), _) => *target,
+        (None, Some(recording)) => recording.0.ticks(),
+        (None, None) => return,
+    };
+    let seek_ticks = (SEEK_SECONDS / tuning.time_step) as u32;
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        playback.seek_to = Some((tick + seek_ticks).min(playback.ticks()));
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        // the simulation only runs forwards, seeking backwards starts the run over
+        playback.seek_to = Some(tick.saturating_sub(seek_ticks));
+        // ignore error from restarting again before the previous restart happened
+        let _ = game_state.restart();
+    }
+}
+
+// Fast-forwards the simulation to the tick being seeked to, over as many frames as needed
+fn playback_seek_system(
+    recording: Option<Res<Recording>>,
+    player_query: Query<&Player>,
+    mut playback: ResMut<Playback>,
+    mut clock: ResMut<SimClock>,
+) {
+    let target = match playback.seek_to {
+        Some(val) => val,
+        None => return,
+    };
+    // fallibility check needed as entities don't exist yet right after a restart
+    let (recording, player) = match (recording, player_query.get_single()) {
+        (Some(recording), Ok(player)) => (recording, player),
+        _ => return,
+    };
+    let tick = recording.0.ticks();
+    if tick >= target || player.collided {
+        playback.seek_to = None;
+        clock.pending_ticks = 0;
+    } else {
+        clock.pending_ticks = (target - tick).min(MAX_SEEK_TICKS_PER_FRAME);
+    }
+}
+
+// Compares the end of the run with the recorded one, reporting a desync when they differ
+fn playback_outcome_system(
+    recording: Option<Res<Recording>>,
+    score: Option<Res<Score>>,
+    player_query: Query<(&Player, &SimPosition)>,
+    mut playback: ResMut<Playback>,
+) {
+    if playback.outcome.is_some() {
+        return;
+    }
+    let (recording, score, (player, position)) = match (recording, score, player_query.get_single())
+    {
+        (Some(recording), Some(score), Ok(val)) => (recording, score.total(), val),
+        _ => return,
+    };
+    let tick = recording.0.ticks();
+    let recorded = &playback.replay;
+    let distance = position.current.x - SPAWN_X;
+    let outcome = if player.collided {
+        let same_end = tick == playback.ticks() && distance == recorded.distance;
+        // replays from before the scores have none to compare with
+        let same_score = match recorded.score {
+            Some(recorded) => recorded == score,
+            None => recorded.version < SCORE_VERSION,
+        };
+        if same_end && same_score {
+            format!("Replay over, score {}, distance {:.2}", score, distance)
+        } else if same_end {
+            format!(
+                "Desync: the run ended with score {}, it was recorded ending with score {}",
+                score,
+                recorded.score.unwrap_or_default()
+            )
+        } else {
+            format!(
+                "Desync: the run ended on tick {} at distance {:.2}, \
+                 it was recorded ending on tick {} at distance {:.2}",
+                tick,
+                distance,
+                playback.ticks(),
+                recorded.distance
+            )
+        }
+    } else if tick > playback.ticks() {
+        format!(
+            "Desync: the run was recorded ending on tick {}, the player is still running",
+            playback.ticks()
+        )
+    } else {
+        return;
+    };
+    if outcome.starts_with("Desync") {
+        warn!("{}", outcome);
+    } else {
+        info!("{}", outcome);
+    }
+    playback.outcome = Some(outcome);
+}
+
+fn playback_text_system(
+    tuning: Res<Tuning>,
+    bindings: Res<InputBindings>,
+    clock: Res<SimClock>,
+    playback: Res<Playback>,
+    recording: Option<Res<Recording>>,
+    mut query: Query<&mut Text, With<PlaybackText>>,
+) {
+    let tick = recording.map_or(0, |recording| recording.0.ticks());
+    for mut text in query.iter_mut() {
+        let mut value = format!(
+            "Replay {}x  {:.1}s / {:.1}s\n\
+             Up/Down: speed  Left/Right: seek  {}: quit",
+            clock.time_scale,
+            tick as f32 * tuning.time_step,
+            playback.ticks() as f32 * tuning.time_step,
+            key_name(bindings.key(Action::Quit)),
+        );
+        if let Some(outcome) = &playback.outcome {
+            value = format!("{}\n{}", outcome, value);
+        }
+        text.sections[0].value = value;
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::collision::CollisionShape;
+use crate::game::{
+    game_setup, CameraRig, GameSystem, OnGameScreen, Pickup, Recording, SimPosition,
+    SimulationTick, REM,
+};
+use crate::highscore::{HighScore, HighScores, Mode};
+use crate::input::{key_name, Action, InputBindings};
+use crate::menu::MenuState;
+use crate::physics::{BoostEnergy, Health, Player, Score};
+use crate::tuning::Tuning;
+
+use super::{GameState, SeedState};
+
+/// Seconds of the run between two snapshots kept for rewinding
+const SNAPSHOT_INTERVAL: f32 = 0.5;
+/// Number of snapshots kept for rewinding, the oldest ones are dropped first
+const SNAPSHOT_COUNT: usize = 20;
+/// Seconds of the run skipped back on a death without a savestate, to get a run-up
+const DEATH_REWIND_SECONDS: f32 = 2.0;
+
+pub struct PracticePlugin;
+
+impl Plugin for PracticePlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .add_system_set(
+                SystemSet::on_enter(GameState::Game).with_system(practice_setup.after(game_setup)),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Game)
+                    .with_system(practice_control_system.before(GameSystem::Simulation)),
+            )
+            // snapshots and restores happen on every tick, at the end of it, so that a death is
+            // undone before the next tick runs
+            .add_system_set(
+                SystemSet::new()
+                    .label(GameSystem::Simulation)
+                    .with_run_criteria(SimulationTick)
+                    .with_system(
+                        practice_system
+                            .after(GameSystem::StateHash)
+                            .after(GameSystem::Score),
+                    ),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Game).with_system(practice_exit));
+    }
+}
+
+/// A run in practice mode: dying goes back to a savestate instead of ending the run
+pub struct Practice;
+
+/// Complete state of a run at the end of a tick. The level is generated up front from the
+/// seed, so apart from the collected pickups there is no level or RNG state to save.
+#[derive(Clone)]
+struct Snapshot {
+    tick: u32,
+    player: Player,
+    health: Health,
+    energy: BoostEnergy,
+    shape: CollisionShape,
+    position: Vec3,
+    camera: Vec3,
+    score: Score,
+    collected: Vec<Entity>,
+}
+
+/// Snapshots of the practice run
+#[derive(Default)]
+struct Savestates {
+    /// Recent snapshots, oldest first, one every `SNAPSHOT_INTERVAL`
+    ring: VecDeque<Snapshot>,
+    /// Snapshot saved with the save state key
+    saved: Option<Snapshot>,
+    /// Practice keys pressed since the last tick, handled on the next one
+    save_requested: bool,
+    load_requested: bool,
+    rewind_requested: bool,
+}
+
+impl Savestates {
+    /// Adds a snapshot of the tick to the ring, if `interval` ticks have passed since the last one
+    fn keep(&mut self, tick: u32, interval: u32, snapshot: impl FnOnce() -> Snapshot) {
+        let due = match self.ring.back() {
+            Some(last) => tick >= last.tick + interval,
+            None => true,
+        };
+        if due {
+            if self.ring.len() == SNAPSHOT_COUNT {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(snapshot());
+        }
+    }
+
+    /// Newest snapshot at least `ticks` ticks before the tick, or the oldest one kept
+    fn before(&self, tick: u32, ticks: u32) -> Option<Snapshot> {
+        self.ring
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.tick + ticks <= tick)
+            .or_else(|| self.ring.front())
+            .cloned()
+    }
+
+    /// Snapshot to rewind to from the tick: the newest one at least `interval` ticks old, so that
+    /// every press goes back one more snapshot even right after the last rewind. The newer
+    /// snapshots are dropped, except for the oldest one kept.
+    fn rewind(&mut self, tick: u32, interval: u32) -> Option<Snapshot> {
+        while self.ring.len() > 1
+            && self
+                .ring
+                .back()
+                .is_some_and(|snapshot| snapshot.tick + interval > tick)
+        {
+            self.ring.pop_back();
+        }
+        self.ring.back().cloned()
+    }
+
+    /// Drops the snapshots taken after the tick the run went back to
+    fn forget_after(&mut self, tick: u32) {
+        self.ring.retain(|kept| kept.tick <= tick);
+    }
+}
+
+// A unit struct to help identify the practice UI component, since there may be many Text components
+#[derive(Component)]
+struct PracticeText;
+
+fn practice_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    bindings: Res<InputBindings>,
+    seed_state: Res<State<SeedState>>,
+    high_scores: Res<HighScores>,
+    practice: Option<Res<Practice>>,
+) {
+    if practice.is_none() {
+        return;
+    }
+    commands.insert_resource(Savestates::default());
+    let best = high_scores
+        .top(Some(seed_state.current().value), Mode::Practice)
+        .next()
+        .map_or(0, |entry| entry.score);
+
+    // practice keys
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(0.0),
+                    right: Val::Px(0.5 * REM),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::with_section(
+                format!(
+                    "Practice, best {}\n{}: save state  {}: load state  {}: rewind  {}: quit",
+                    best,
+                    key_name(bindings.key(Action::SaveState)),
+                    key_name(bindings.key(Action::LoadState)),
+                    key_name(bindings.key(Action::Rewind)),
+                    key_name(bindings.key(Action::Quit)),
+                ),
+                TextStyle {
+                    font: asset_server.load("fonts/undefined-medium.ttf"),
+                    font_size: REM,
+                    color: Color::WHITE,
+                },
+                TextAlignment {
+                    horizontal: HorizontalAlign::Right,
+                    ..default()
+                },
+            ),
+            ..default()
+        })
+        .insert(OnGameScreen)
+        .insert(PracticeText);
+}
+
+// Handles the practice keys once per frame: quits right away, and leaves the savestate keys
+// to the next tick, so that a key press is handled once however many ticks the frame runs
+fn practice_control_system(
+    actions: Res<Input<Action>>,
+    savestates: Option<ResMut<Savestates>>,
+    score: Option<Res<Score>>,
+    seed_state: Res<State<SeedState>>,
+    mut high_scores: ResMut<HighScores>,
+    mut game_state: ResMut<State<GameState>>,
+    mut menu_state: ResMut<State<MenuState>>,
+) {
+    // fallibility check needed as the savestates only exist in practice
+    let (mut savestates, score) = match (savestates, score) {
+        (Some(savestates), Some(score)) => (savestates, score),
+        _ => return,
+    };
+    if actions.just_pressed(Action::Quit) {
+        // a practice run has no game over, it ends here
+        let seed = seed_state.current().value;
+        let entry = HighScore::new(seed, &score, Mode::Practice, None);
+        if high_scores.insert(entry).is_some() {
+            high_scores.save();
+        }
+        let _ = game_state.set(GameState::MainMenu);
+        let _ = menu_state.set(MenuState::MainMenu);
+        return;
+    }
+    savestates.save_requested |= actions.just_pressed(Action::SaveState);
+    savestates.load_requested |= actions.just_pressed(Action::LoadState);
+    savestates.rewind_requested |= actions.just_pressed(Action::Rewind);
+}
+
+// Takes the snapshots at the end of each tick, and restores them on the practice keys and on death
+fn practice_system(
+    tuning: Res<Tuning>,
+    savestates: Option<ResMut<Savestates>>,
+    recording: Option<ResMut<Recording>>,
+    score: Option<ResMut<Score>>,
+    mut player_query: Query<
+        (
+            &mut Player,
+            &mut Health,
+            &mut BoostEnergy,
+            &mut CollisionShape,
+            &mut SimPosition,
+        ),
+        Without<CameraRig>,
+    >,
+    mut camera_query: Query<&mut SimPosition, (With<CameraRig>, Without<Player>)>,
+    mut pickup_query: Query<(Entity, &mut Visibility), With<Pickup>>,
+) {
+    // fallibility check needed as the savestates only exist in practice
+    let (mut savestates, mut recording, mut score) = match (savestates, recording, score) {
+        (Some(savestates), Some(recording), Some(score)) => (savestates, recording, score),
+        _ => return,
+    };
+    let (mut player, mut health, mut energy, mut shape, mut position) =
+        match player_query.get_single_mut() {
+            Ok(val) => val,
+            Err(_) => return,
+        };
+    let mut camera = match camera_query.get_single_mut() {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+
+    let tick = recording.0.ticks();
+    let savestates = &mut *savestates;
+    let save = std::mem::take(&mut savestates.save_requested);
+    let load = std::mem::take(&mut savestates.load_requested);
+    let rewind = std::mem::take(&mut savestates.rewind_requested);
+    let interval = (SNAPSHOT_INTERVAL / tuning.time_step) as u32;
+    let restore = if player.collided {
+        let death_rewind = (DEATH_REWIND_SECONDS / tuning.time_step) as u32;
+        savestates
+            .saved
+            .clone()
+            .or_else(|| savestates.before(tick, death_rewind))
+    } else if load {
+        savestates.saved.clone()
+    } else if rewind {
+        savestates.rewind(tick, interval)
+    } else {
+        None
+    };
+
+    if let Some(snapshot) = restore {
+        *player = snapshot.player;
+        *health = snapshot.health;
+        *energy = snapshot.energy;
+        *shape = snapshot.shape;
+        position.teleport(snapshot.position);
+        camera.teleport(snapshot.camera);
+        *score = snapshot.score;
+        for (entity, mut visibility) in pickup_query.iter_mut() {
+            visibility.is_visible = !snapshot.collected.contains(&entity);
+        }
+        // the run goes on from the snapshot, as if what came after it never happened
+        recording.0.truncate(snapshot.tick);
+        savestates.forget_after(snapshot.tick);
+        return;
+    }
+
+    let snapshot = || Snapshot {
+        tick,
+        player: player.clone(),
+        health: health.clone(),
+        energy: energy.clone(),
+        shape: *shape,
+        position: position.current,
+        camera: camera.current,
+        score: score.clone(),
+        collected: pickup_query
+            .iter()
+            .filter(|(_, visibility)| !visibility.is_visible)
+            .map(|(entity, _)| entity)
+            .collect(),
+    };
+    if save {
+        savestates.saved = Some(snapshot());
+    }
+    savestates.keep(tick, interval, snapshot);
+}
+
+// Leaving practice goes straight to the main menu, without the game over screen cleaning up
+fn practice_exit(
+    mut commands: Commands,
+    practice: Option<Res<Practice>>,
+    query: Query<Entity, With<OnGameScreen>>,
+) {
+    if practice.is_none() {
+        return;
+    }
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<Practice>();
+    commands.remove_resource::<Savestates>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: u32 = 10;
+
+    fn snapshot(tick: u32) -> Snapshot {
+        Snapshot {
+            tick,
+            player: Player::default(),
+            health: Health::new(1),
+            energy: BoostEnergy { current: 0.0 },
+            shape: CollisionShape {
+                radius: 1.0,
+                offset_y: 0.0,
+            },
+            position: Vec3::ZERO,
+            camera: Vec3::ZERO,
+            score: Score::default(),
+            collected: Vec::new(),
+        }
+    }
+
+    // Runs the ticks after `from`, up to `to`, keeping the snapshots like the game does
+    fn play(savestates: &mut Savestates, from: u32, to: u32) {
+        for tick in from + 1..=to {
+            savestates.keep(tick, INTERVAL, || snapshot(tick));
+        }
+    }
+
+    fn ticks(savestates: &Savestates) -> Vec<u32> {
+        savestates
+            .ring
+            .iter()
+            .map(|snapshot| snapshot.tick)
+            .collect()
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_snapshots() {
+        let mut savestates = Savestates::default();
+        play(&mut savestates, 0, INTERVAL * SNAPSHOT_COUNT as u32 + 5);
+        assert_eq!(savestates.ring.len(), SNAPSHOT_COUNT);
+        assert_eq!(savestates.ring.front().unwrap().tick, INTERVAL + 1);
+    }
+
+    #[test]
+    fn rewinds_walk_back_through_the_ring() {
+        let mut savestates = Savestates::default();
+        play(&mut savestates, 0, 55);
+        assert_eq!(ticks(&savestates), [1, 11, 21, 31, 41, 51]);
+
+        // a few ticks pass after every rewind, not enough for a new snapshot
+        let mut tick = 55;
+        for expected in [41, 31, 21, 11, 1, 1] {
+            let restored = savestates.rewind(tick, INTERVAL).unwrap();
+            assert_eq!(restored.tick, expected);
+            savestates.forget_after(restored.tick);
+            tick = restored.tick + 3;
+            play(&mut savestates, restored.tick, tick);
+        }
+        assert_eq!(ticks(&savestates), [1]);
+    }
+
+    #[test]
+    fn rewind_skips_a_snapshot_just_taken() {
+        let mut savestates = Savestates::default();
+        play(&mut savestates, 0, 35);
+        assert_eq!(savestates.rewind(35, INTERVAL).unwrap().tick, 21);
+        assert_eq!(ticks(&savestates), [1, 11, 21]);
+    }
+
+    #[test]
+    fn death_goes_back_far_enough() {
+        let mut savestates = Savestates::default();
+        play(&mut savestates, 0, 55);
+        assert_eq!(savestates.before(55, 20).unwrap().tick, 31);
+        // early in the run, back to the start
+        assert_eq!(savestates.before(55, 100).unwrap().tick, 1);
+    }
+}
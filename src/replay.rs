@@ -0,0 +1,263 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::level::{GENERATOR_VERSION, SPAWN_X};
+use crate::physics::hash_difference;
+use crate::storage;
+use crate::tuning::Tuning;
+
+/// Directory the replays are written to, inside the game's data directory
+const REPLAY_DIR: &str = "replays";
+/// Extension of the replay files
+pub const REPLAY_EXTENSION: &str = "replay.ron";
+/// Version of the replay format, bumped whenever a field changes meaning
+pub const REPLAY_VERSION: u32 = 2;
+/// First `REPLAY_VERSION` whose replays always hold the score
+pub const SCORE_VERSION: u32 = 2;
+
+/// State of the inputs that affect the simulation, sampled once per tick
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TickInput {
+    pub jump: bool,
+    pub boost: bool,
+    pub down: bool,
+}
+
+impl TickInput {
+    fn to_bits(self) -> u8 {
+        self.jump as u8 | (self.boost as u8) << 1 | (self.down as u8) << 2
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            jump: bits & 1 != 0,
+            boost: bits & 1 << 1 != 0,
+            down: bits & 1 << 2 != 0,
+        }
+    }
+}
+
+/// Everything needed to simulate a run again: the level, the gameplay parameters and the
+/// input of every tick, along with the outcome of the run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    /// `GENERATOR_VERSION` of the level generator the run was played with
+    pub generator_version: u32,
+    /// `REPLAY_VERSION` the replay was written with, 0 for replays from before the versions
+    #[serde(default)]
+    pub version: u32,
+    pub seed: u64,
+    pub tuning: Tuning,
+    /// Inputs of consecutive ticks, run-length encoded as the input bits and the number of
+    /// ticks they were held for
+    inputs: Vec<(u8, u32)>,
+    /// State hash at the end of every recorded tick, see `physics::state_hash`.
+    /// Empty in replays recorded before the hashes were added.
+    #[serde(default)]
+    hashes: Vec<u64>,
+    /// Distance from the spawn point to where the run ended
+    pub distance: f32,
+    /// Final score of the run. Only missing in replays older than `SCORE_VERSION`.
+    #[serde(default)]
+    pub score: Option<u32>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, tuning: Tuning) -> Self {
+        Self {
+            generator_version: GENERATOR_VERSION,
+            version: REPLAY_VERSION,
+            seed,
+            tuning,
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+            distance: 0.0,
+            score: None,
+        }
+    }
+
+    /// Appends the input of the next tick
+    pub fn push(&mut self, input: TickInput) {
+        let bits = input.to_bits();
+        match self.inputs.last_mut() {
+            Some((last, count)) if *last == bits => *count += 1,
+            _ => self.inputs.push((bits, 1)),
+        }
+    }
+
+    /// Appends the state hash at the end of the next tick
+    pub fn push_hash(&mut self, hash: u64) {
+        self.hashes.push(hash);
+    }
+
+    /// Number of recorded state hashes
+    pub fn hash_count(&self) -> u32 {
+        self.hashes.len() as u32
+    }
+
+    /// Describes how the state hash of the tick differs from the recorded one, if it does
+    pub fn check_hash(&self, tick: u32, hash: u64) -> Option<String> {
+        let recorded = *self.hashes.get(tick as usize)?;
+        hash_difference(recorded, hash)
+            .map(|field| format!("Desync on tick {}: {} differs", tick, field))
+    }
+
+    /// Drops the inputs and state hashes recorded after the first `ticks` ticks
+    pub fn truncate(&mut self, ticks: u32) {
+        let mut remaining = ticks;
+        let mut runs = 0;
+        for (_, count) in self.inputs.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            *count = (*count).min(remaining);
+            remaining -= *count;
+            runs += 1;
+        }
+        self.inputs.truncate(runs);
+        self.hashes.truncate(ticks as usize);
+    }
+
+    /// Number of recorded ticks
+    pub fn ticks(&self) -> u32 {
+        self.inputs.iter().map(|&(_, count)| count).sum()
+    }
+
+    /// Input of every recorded tick, in order
+    pub fn inputs(&self) -> Vec<TickInput> {
+        self.inputs
+            .iter()
+            .flat_map(|&(bits, count)| (0..count).map(move |_| TickInput::from_bits(bits)))
+            .collect()
+    }
+
+    /// Reads a replay, refusing the ones recorded with another version of the level generator
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let mut replay = ron::from_str::<Replay>(&text).map_err(|error| error.to_string())?;
+        if replay.generator_version != GENERATOR_VERSION {
+            return Err(format!(
+                "recorded with level generator version {}, this build has version {}",
+                replay.generator_version, GENERATOR_VERSION
+            ));
+        }
+        // the first replays stored the x coordinate the run ended at
+        if replay.version == 0 {
+            replay.distance -= SPAWN_X;
+        }
+        Ok(replay)
+    }
+
+    fn dir() -> Option<PathBuf> {
+        storage::path(dirs::data_dir(), REPLAY_DIR)
+    }
+
+    /// Writes the replay to a new file in the replays directory, named after the time and seed
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let dir = Replay::dir().ok_or("no data directory on this system")?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let path = dir.join(format!(
+            "{}-{:016x}.{}",
+            timestamp, self.seed, REPLAY_EXTENSION
+        ));
+        let text = ron::to_string(self).map_err(|error| error.to_string())?;
+        fs::create_dir_all(&dir).map_err(|error| error.to_string())?;
+        fs::write(&path, text).map_err(|error| error.to_string())?;
+        Ok(path)
+    }
+}
+
+/// Replay being played back, feeding its inputs to the simulation instead of the player's
+pub struct Playback {
+    pub replay: Replay,
+    inputs: Vec<TickInput>,
+    /// Tick to fast-forward to
+    pub seek_to: Option<u32>,
+    /// Whether the run simulated the same as it was recorded, once it has ended
+    pub outcome: Option<String>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            inputs: replay.inputs(),
+            replay,
+            seek_to: None,
+            outcome: None,
+        }
+    }
+
+    /// Number of ticks in the replay
+    pub fn ticks(&self) -> u32 {
+        self.inputs.len() as u32
+    }
+
+    /// Recorded input for the tick, nothing pressed past the end of the replay
+    pub fn input(&self, tick: u32) -> TickInput {
+        self.inputs.get(tick as usize).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JUMP: TickInput = TickInput {
+        jump: true,
+        boost: false,
+        down: false,
+    };
+
+    // 3 ticks of nothing, 2 of jumping, and 4 of nothing, with a hash after each
+    fn recorded() -> Replay {
+        let mut replay = Replay::new(0, Tuning::default());
+        let inputs = [
+            (TickInput::default(), 3),
+            (JUMP, 2),
+            (TickInput::default(), 4),
+        ]
+        .into_iter()
+        .flat_map(|(input, count)| (0..count).map(move |_| input));
+        for (tick, input) in inputs.enumerate() {
+            replay.push(input);
+            replay.push_hash(tick as u64);
+        }
+        replay
+    }
+
+    #[test]
+    fn truncate_cuts_through_a_run_of_inputs() {
+        let mut replay = recorded();
+        replay.truncate(4);
+        assert_eq!(replay.inputs, [(0, 3), (JUMP.to_bits(), 1)]);
+        assert_eq!(replay.ticks(), 4);
+        assert_eq!(replay.hash_count(), 4);
+        // recording goes on from the cut, merging with the run it ended in
+        replay.push(JUMP);
+        assert_eq!(replay.inputs, [(0, 3), (JUMP.to_bits(), 2)]);
+    }
+
+    #[test]
+    fn truncate_on_a_run_boundary_drops_the_later_runs() {
+        let mut replay = recorded();
+        replay.truncate(5);
+        assert_eq!(replay.inputs, [(0, 3), (JUMP.to_bits(), 2)]);
+        replay.truncate(0);
+        assert!(replay.inputs.is_empty());
+        assert_eq!(replay.hash_count(), 0);
+    }
+
+    #[test]
+    fn truncate_past_the_end_keeps_everything() {
+        let mut replay = recorded();
+        replay.truncate(100);
+        assert_eq!(replay.ticks(), 9);
+        assert_eq!(replay.inputs(), recorded().inputs());
+    }
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// Scroll speed of a run as a function of the distance travelled from the spawn point
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SpeedCurve {
+    /// Scroll velocity at the spawn point
+    pub base: f32,
+    /// Scroll velocity gained per unit of distance
+    pub ramp: f32,
+    /// Cap for the scroll velocity
+    pub max: f32,
+    /// Velocity added on top of the scroll velocity while boosting
+    pub boost: f32,
+}
+
+impl SpeedCurve {
+    pub fn scroll_velocity(&self, distance: f32) -> f32 {
+        (self.base + self.ramp * distance.max(0.0)).min(self.max)
+    }
+
+    pub fn boost_velocity(&self, distance: f32) -> f32 {
+        self.scroll_velocity(distance) + self.boost
+    }
+
+    /// How many times faster than at the spawn point the level scrolls at the given distance.
+    /// Jump physics and obstacle spacing scale with this to keep the level equally fair.
+    pub fn speed_factor(&self, distance: f32) -> f32 {
+        self.scroll_velocity(distance) / self.base
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::SimClock;
+use crate::game::{BoostEvent, CollisionEvent, GameSystem, JumpEvent};
+use crate::level::ObstacleKind;
+use crate::physics::Score;
+use crate::storage;
+
+use super::GameState;
+
+/// File the statistics are saved to, in the data directory
+const STATISTICS_FILE: &str = "statistics.ron";
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .insert_resource(Statistics::load())
+            .add_system_set(SystemSet::on_enter(GameState::Game).with_system(run_start_system))
+            // counts the events of every tick of the frame, the one ending the run included
+            .add_system(statistics_system.after(GameSystem::Simulation))
+            .add_system_set(SystemSet::on_exit(GameState::Game).with_system(run_end_system));
+    }
+}
+
+/// Totals over every run played, practice included, but not playbacks
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Statistics {
+    pub runs: u32,
+    pub jumps: u32,
+    pub boosts: u32,
+    /// Distance from the spawn point, summed over the runs
+    pub distance: f64,
+    /// Runs ended by each kind of obstacle
+    pub deaths: BTreeMap<ObstacleKind, u32>,
+    /// Seconds played, pauses left out
+    pub playtime: f64,
+    /// Most clean jumps in a row, see `Bonus::CleanJump`
+    pub best_combo: u32,
+}
+
+impl Statistics {
+    /// Reads the saved statistics. Like the high scores, a corrupt file is moved aside and the
+    /// counts start over.
+    pub fn load() -> Self {
+        storage::load(dirs::data_dir(), STATISTICS_FILE, "statistics").unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        storage::save(dirs::data_dir(), STATISTICS_FILE, "statistics", self);
+    }
+
+    /// One line per statistic, for the statistics screen
+    pub fn summary(&self) -> String {
+        let seconds = self.playtime as u64;
+        let deaths = |kind| self.deaths.get(&kind).copied().unwrap_or_default();
+        format!(
+            "Runs: {}\n\
+             Playtime: {}:{:02}:{:02}\n\
+             Distance: {:.1}\n\
+             Jumps: {}\n\
+             Boosts: {}\n\
+             Deaths on the ground: {}\n\
+             Deaths under ceilings: {}\n\
+             Best clean jump streak: {}",
+            self.runs,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            self.distance,
+            self.jumps,
+            self.boosts,
+            deaths(ObstacleKind::Ground),
+            deaths(ObstacleKind::Ceiling),
+            self.best_combo,
+        )
+    }
+}
+
+fn run_start_system(mut statistics: ResMut<Statistics>) {
+    statistics.runs += 1;
+}
+
+fn statistics_system(
+    time: Res<Time>,
+    clock: Res<SimClock>,
+    game_state: Res<State<GameState>>,
+    score: Option<Res<Score>>,
+    mut jump_events: EventReader<JumpEvent>,
+    mut boost_events: EventReader<BoostEvent>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut statistics: ResMut<Statistics>,
+) {
+    let jumps = jump_events.iter().count() as u32;
+    let boosts = boost_events.iter().count() as u32;
+    let deaths: Vec<ObstacleKind> = collision_events
+        .iter()
+        .filter(|event| event.fatal)
+        .map(|event| event.kind)
+        .collect();
+    // the events of a playback, or of the ticks simulated after the run, are not played
+    if *game_state.current() != GameState::Game {
+        return;
+    }
+
+    statistics.jumps += jumps;
+    statistics.boosts += boosts;
+    for kind in deaths {
+        *statistics.deaths.entry(kind).or_default() += 1;
+    }
+    if !clock.paused {
+        statistics.playtime += time.delta_seconds_f64();
+    }
+    if let Some(score) = score {
+        statistics.best_combo = statistics.best_combo.max(score.combo);
+    }
+}
+
+// Adds the distance of the run that just ended, and saves the totals
+fn run_end_system(score: Option<Res<Score>>, mut statistics: ResMut<Statistics>) {
+    if let Some(score) = score {
+        statistics.distance += score.distance as f64;
+    }
+    statistics.save();
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Directory of the game's files, inside the user's data or config directory
+const GAME_DIR: &str = "jump";
+
+/// Path of `file` in the game's directory inside `base`, such as `dirs::data_dir()`
+pub fn path(base: Option<PathBuf>, file: &str) -> Option<PathBuf> {
+    base.map(|dir| dir.join(GAME_DIR).join(file))
+}
+
+/// Reads `file` from the game's directory inside `base`, such as `dirs::data_dir()`, as RON.
+/// Returns `None` when there is no such file yet. A file that cannot be read is moved aside to
+/// `<file>.corrupt` rather than overwritten by the next save, and `what` names its contents in
+/// the log.
+pub fn load<T: DeserializeOwned>(base: Option<PathBuf>, file: &str, what: &str) -> Option<T> {
+    let path = path(base, file)?;
+    let error = match fs::read_to_string(&path) {
+        Ok(text) => match ron::from_str::<T>(&text) {
+            Ok(val) => return Some(val),
+            Err(error) => error.to_string(),
+        },
+        // nothing saved yet
+        Err(error) if error.kind() == ErrorKind::NotFound => return None,
+        Err(error) => error.to_string(),
+    };
+    let corrupt = path.with_extension("ron.corrupt");
+    warn!(
+        "Ignoring invalid {} in {:?}, moving them to {:?}: {}",
+        what, path, corrupt, error
+    );
+    if let Err(error) = fs::rename(&path, &corrupt) {
+        warn!("Could not move the invalid {}: {}", what, error);
+    }
+    None
+}
+
+/// Writes `value` as RON to `file` in the game's directory inside `base`, creating the
+/// directory if needed
+pub fn save<T: Serialize>(base: Option<PathBuf>, file: &str, what: &str, value: &T) {
+    let path = match path(base, file) {
+        Some(val) => val,
+        None => return,
+    };
+    let result = ron::ser::to_string_pretty(value, Default::default())
+        .map_err(|error| error.to_string())
+        .and_then(|text| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+            }
+            fs::write(&path, text).map_err(|error| error.to_string())
+        });
+    if let Err(error) = result {
+        warn!("Could not save {} to {:?}: {}", what, path, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own in the temporary directory, cleared of any previous test run
+    fn temp_base(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("jump-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&base);
+        base
+    }
+
+    #[test]
+    fn saved_file_loads_back() {
+        let base = temp_base("saved");
+        save(Some(base.clone()), "numbers.ron", "numbers", &vec![1, 2, 3]);
+        let loaded: Option<Vec<u32>> = load(Some(base.clone()), "numbers.ron", "numbers");
+        assert_eq!(loaded, Some(vec![1, 2, 3]));
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn missing_file_loads_nothing() {
+        let base = temp_base("missing");
+        let loaded: Option<Vec<u32>> = load(Some(base.clone()), "numbers.ron", "numbers");
+        assert_eq!(loaded, None);
+        assert!(!base.exists());
+    }
+
+    #[test]
+    fn corrupt_files_are_moved_aside() {
+        let base = temp_base("corrupt");
+        let dir = base.join(GAME_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        // not RON, and not even text
+        for contents in [&b"[1, 2"[..], &[0xff, 0xfe, 0x00][..]] {
+            fs::write(dir.join("numbers.ron"), contents).unwrap();
+            let loaded: Option<Vec<u32>> = load(Some(base.clone()), "numbers.ron", "numbers");
+            assert_eq!(loaded, None);
+            assert!(!dir.join("numbers.ron").exists());
+            assert_eq!(fs::read(dir.join("numbers.ron.corrupt")).unwrap(), contents);
+        }
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,356 @@
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+use crate::game::REM;
+use crate::speed::SpeedCurve;
+
+/// Tuning file loaded at startup, and reloaded whenever it changes on disk
+const TUNING_PATH: &str = "gameplay.tuning.ron";
+
+/// The tuning file as shipped, used until the file is loaded. Keeping the defaults in the
+/// file alone means there is a single set of values to keep up to date.
+const DEFAULT_TUNING: &str = include_str!("../assets/gameplay.tuning.ron");
+
+pub struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app // load-bearing comment, better readability for chains below
+            .init_resource::<Tuning>()
+            .init_resource::<FileTuning>()
+            .init_resource::<TuningOverride>()
+            .add_asset::<TuningAsset>()
+            .init_asset_loader::<TuningLoader>()
+            .add_startup_system(tuning_setup)
+            .add_system(tuning_update_system)
+            .add_system(tuning_apply_system.after(tuning_update_system));
+    }
+}
+
+/// Gameplay parameters. Everything that affects the feel of the game lives here,
+/// so that it can be iterated on without recompiling.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Tuning {
+    /// Lockstep for the game engine, in seconds
+    pub time_step: f32,
+    /// Initial upwards velocity for the jump at the starting scroll speed
+    pub jump_initial_velocity: f32,
+    /// Gravity constant for the jump at the starting scroll speed
+    pub gravity: f32,
+    /// Gravity multiplier while fast-falling
+    pub fast_fall_gravity: f32,
+    /// Radius of the spheres, both for player and obstacles
+    pub sphere_radius: f32,
+    /// Size of the player's collision sphere while ducking, relative to `sphere_radius`
+    pub crouch_scale: f32,
+    /// Scroll and boost velocities over the run
+    pub speed_curve: SpeedCurve,
+    /// Number of hits the player can take, the last one ends the run
+    pub lives: u32,
+    /// Seconds after a hit during which the player cannot be hit again
+    pub invulnerability_time: f32,
+    /// Backwards velocity applied to the player on a hit
+    pub knockback_velocity_x: f32,
+    /// Upwards velocity applied to the player on a hit
+    pub knockback_velocity_y: f32,
+    /// How fast the backwards knockback velocity recovers back to zero
+    pub knockback_recovery: f32,
+    /// How the camera follows the player
+    pub camera: CameraSettings,
+    /// Energy spent on boosting
+    pub boost_energy: BoostEnergySettings,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        ron::from_str(DEFAULT_TUNING).expect("the shipped tuning file is valid")
+    }
+}
+
+/// Parameters for the camera rig following the player
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CameraSettings {
+    /// Horizontal distance the camera keeps ahead of the player
+    pub offset_x: f32,
+    /// Seconds of extra speed, over the starting scroll speed, that the camera looks ahead
+    pub look_ahead: f32,
+    /// Rate at which the camera catches up horizontally, per second
+    pub horizontal_follow: f32,
+    /// Rate at which the camera catches up vertically while the player is supported, per second
+    pub vertical_follow: f32,
+    /// Rate at which the camera catches up vertically during jumps, per second.
+    /// Lower than `vertical_follow` so that jumps do not shake the view.
+    pub vertical_follow_in_air: f32,
+    /// Distance the camera pulls back per unit of speed over the starting scroll speed,
+    /// zero to disable
+    pub zoom_out: f32,
+}
+
+/// Parameters for the energy meter limiting the boost, measured in seconds of boosting
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BoostEnergySettings {
+    /// Energy of a full meter, also the energy at the start of a run
+    pub max: f32,
+    /// Energy spent per second of boosting
+    pub drain: f32,
+    /// Energy regained per second while not boosting
+    pub recharge: f32,
+    /// Energy gained from a pickup
+    pub pickup: f32,
+    /// Energy needed to start a boost, once started it lasts until the meter is empty.
+    /// Defaults to the value the replays recorded before this setting were played with.
+    #[serde(default = "BoostEnergySettings::default_min_to_start")]
+    pub min_to_start: f32,
+}
+
+impl BoostEnergySettings {
+    fn default_min_to_start() -> f32 {
+        0.25
+    }
+}
+
+impl Tuning {
+    /// Checks that the values make for a playable game, listing every problem found
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        let mut positive = |name: &str, value: f32| {
+            if !(value > 0.0 && value.is_finite()) {
+                errors.push(format!("{} must be positive, got {}", name, value));
+            }
+        };
+        positive("time_step", self.time_step);
+        positive("jump_initial_velocity", self.jump_initial_velocity);
+        positive("gravity", self.gravity);
+        positive("fast_fall_gravity", self.fast_fall_gravity);
+        positive("sphere_radius", self.sphere_radius);
+        positive("crouch_scale", self.crouch_scale);
+        positive("speed_curve.base", self.speed_curve.base);
+        positive("speed_curve.max", self.speed_curve.max);
+        positive("knockback_recovery", self.knockback_recovery);
+        positive("camera.horizontal_follow", self.camera.horizontal_follow);
+        positive("camera.vertical_follow", self.camera.vertical_follow);
+        positive(
+            "camera.vertical_follow_in_air",
+            self.camera.vertical_follow_in_air,
+        );
+        positive("boost_energy.max", self.boost_energy.max);
+
+        let mut non_negative = |name: &str, value: f32| {
+            if !(value >= 0.0 && value.is_finite()) {
+                errors.push(format!("{} must not be negative, got {}", name, value));
+            }
+        };
+        non_negative("speed_curve.ramp", self.speed_curve.ramp);
+        non_negative("speed_curve.boost", self.speed_curve.boost);
+        non_negative("invulnerability_time", self.invulnerability_time);
+        non_negative("knockback_velocity_x", self.knockback_velocity_x);
+        non_negative("knockback_velocity_y", self.knockback_velocity_y);
+        non_negative("camera.look_ahead", self.camera.look_ahead);
+        non_negative("camera.zoom_out", self.camera.zoom_out);
+        non_negative("boost_energy.drain", self.boost_energy.drain);
+        non_negative("boost_energy.recharge", self.boost_energy.recharge);
+        non_negative("boost_energy.pickup", self.boost_energy.pickup);
+        non_negative("boost_energy.min_to_start", self.boost_energy.min_to_start);
+
+        if self.time_step > 0.1 {
+            errors.push(format!(
+                "time_step must be at most 0.1, got {}",
+                self.time_step
+            ));
+        }
+        if self.crouch_scale > 1.0 {
+            errors.push(format!(
+                "crouch_scale must be at most 1, got {}",
+                self.crouch_scale
+            ));
+        }
+        if self.speed_curve.max < self.speed_curve.base {
+            errors.push(format!(
+                "speed_curve.max must be at least speed_curve.base, got {} < {}",
+                self.speed_curve.max, self.speed_curve.base
+            ));
+        }
+        if self.boost_energy.min_to_start > self.boost_energy.max {
+            errors.push(format!(
+                "boost_energy.min_to_start must be at most boost_energy.max, got {} > {}",
+                self.boost_energy.min_to_start, self.boost_energy.max
+            ));
+        }
+        if self.lives == 0 {
+            errors.push("lives must be at least 1".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// A tuning file as loaded from the assets. Parse and validation errors are kept
+/// in the asset instead of failing the load, so that they can be shown on screen.
+#[derive(TypeUuid)]
+#[uuid = "3f1a6c52-8d0e-4b3f-9a57-1c2e7d9b4f60"]
+pub struct TuningAsset(Result<Tuning, String>);
+
+#[derive(Default)]
+struct TuningLoader;
+
+impl AssetLoader for TuningLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let tuning = ron::de::from_bytes::<Tuning>(bytes)
+                .map_err(|error| error.to_string())
+                .and_then(|tuning| tuning.validate().map(|_| tuning));
+            load_context.set_default_asset(LoadedAsset::new(TuningAsset(tuning)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tuning.ron"]
+    }
+}
+
+/// Tuning used instead of the tuning file while set, such as the tuning a replay was recorded with
+#[derive(Default)]
+pub struct TuningOverride(pub Option<Tuning>);
+
+// Last valid tuning loaded from the tuning file
+#[derive(Default)]
+struct FileTuning(Tuning);
+
+// Handle kept alive so that the tuning file stays loaded and watched
+struct TuningHandle(Handle<TuningAsset>);
+
+// A unit struct to help identify the tuning error UI component, since there may be many Text components
+#[derive(Component)]
+struct TuningErrorText;
+
+fn tuning_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(TuningHandle(asset_server.load(TUNING_PATH)));
+
+    // validation errors, shown on top of every screen until the file is fixed
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(2.0 * REM),
+                    left: Val::Px(0.5 * REM),
+                    ..default()
+                },
+                ..default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/undefined-medium.ttf"),
+                    font_size: REM,
+                    color: Color::RED,
+                },
+                Default::default(),
+            ),
+            ..default()
+        })
+        .insert(TuningErrorText);
+}
+
+// Applies the tuning file whenever it is loaded or modified, keeping the previous values on errors
+fn tuning_update_system(
+    mut events: EventReader<AssetEvent<TuningAsset>>,
+    assets: Res<Assets<TuningAsset>>,
+    asset_server: Res<AssetServer>,
+    handle: Res<TuningHandle>,
+    mut load_failed: Local<bool>,
+    mut file_tuning: ResMut<FileTuning>,
+    mut query: Query<&mut Text, With<TuningErrorText>>,
+) {
+    // a file that is missing or cannot be read sends no asset event, only fails to load
+    let failed = asset_server.get_load_state(&handle.0) == LoadState::Failed;
+    if failed && !*load_failed {
+        warn!("Could not load {}, using the default tuning", TUNING_PATH);
+        for mut text in query.iter_mut() {
+            text.sections[0].value = format!(
+                "Could not load {}:\nusing the default tuning, see the log for details",
+                TUNING_PATH
+            );
+        }
+    }
+    *load_failed = failed;
+
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: changed } | AssetEvent::Modified { handle: changed }
+                if *changed == handle.0 => {}
+            _ => continue,
+        }
+        let asset = match assets.get(&handle.0) {
+            Some(val) => val,
+            None => continue,
+        };
+        let message = match &asset.0 {
+            Ok(new_tuning) => {
+                file_tuning.0 = new_tuning.clone();
+                info!("Loaded tuning from {}", TUNING_PATH);
+                String::new()
+            }
+            Err(error) => {
+                warn!("Invalid tuning in {}: {}", TUNING_PATH, error);
+                format!("Invalid {}:\n{}", TUNING_PATH, error)
+            }
+        };
+        for mut text in query.iter_mut() {
+            text.sections[0].value = message.clone();
+        }
+    }
+}
+
+// Keeps the `Tuning` resource in sync with the tuning file, or with the override when set
+fn tuning_apply_system(
+    file_tuning: Res<FileTuning>,
+    tuning_override: Res<TuningOverride>,
+    mut tuning: ResMut<Tuning>,
+) {
+    if !file_tuning.is_changed() && !tuning_override.is_changed() {
+        return;
+    }
+    let new_tuning = tuning_override.0.as_ref().unwrap_or(&file_tuning.0);
+    if *tuning != *new_tuning {
+        *tuning = new_tuning.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_tuning_is_valid() {
+        assert_eq!(Tuning::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn boost_start_energy_is_checked_against_the_meter() {
+        let mut tuning = Tuning::default();
+        tuning.boost_energy.min_to_start = tuning.boost_energy.max + 1.0;
+        let error = tuning.validate().unwrap_err();
+        assert!(error.contains("min_to_start"), "{}", error);
+    }
+
+    #[test]
+    fn tuning_from_older_replays_still_loads() {
+        let older = DEFAULT_TUNING.replace("min_to_start:", "// min_to_start:");
+        let tuning: Tuning = ron::from_str(&older).unwrap();
+        assert_eq!(tuning.boost_energy.min_to_start, 0.25);
+    }
+}
@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::level::{Level, SPAWN_X};
+use crate::physics::{Course, Runner};
+use crate::replay::{Replay, REPLAY_EXTENSION, SCORE_VERSION};
+use crate::tuning::Tuning;
+
+/// End of a replay's run, as simulated again from its inputs
+#[derive(Debug)]
+pub struct Simulated {
+    pub ticks: u32,
+    /// Distance from the spawn point to where the run ended
+    pub distance: f32,
+    pub score: u32,
+    /// Whether the run ended with the hit costing the last life
+    pub ended: bool,
+    /// First tick whose state hash differs from the recorded one
+    pub desync: Option<String>,
+}
+
+/// Simulates the replay without a window, with the same physics as the game
+pub fn simulate(replay: &Replay) -> Simulated {
+    let tuning = &replay.tuning;
+    let level = Level::new(replay.seed, &tuning.speed_curve);
+    let course = Course::new(&level, tuning);
+    let mut runner = Runner::new(&course, tuning);
+    let mut ticks = 0;
+    let mut desync = None;
+    for input in replay.inputs() {
+        if runner.player.collided {
+            break;
+        }
+        runner.step(input, &course, tuning);
+        if desync.is_none() {
+            desync = replay.check_hash(ticks, runner.state_hash());
+        }
+        ticks += 1;
+    }
+    Simulated {
+        ticks,
+        distance: runner.position.x - SPAWN_X,
+        score: runner.score.total(),
+        ended: runner.player.collided,
+        desync,
+    }
+}
+
+/// Checks that the replay simulates to the end it claims, with a valid tuning. When a tuning is
+/// required, the replay must also have been recorded with it, as a run is only comparable
+/// with the runs played with the same parameters.
+pub fn verify(replay: &Replay, required_tuning: Option<&Tuning>) -> Result<Simulated, String> {
+    if let Err(error) = replay.tuning.validate() {
+        return Err(format!("invalid tuning: {}", error.replace('\n', ", ")));
+    }
+    if required_tuning.is_some_and(|tuning| replay.tuning != *tuning) {
+        return Err("recorded with a tuning other than the shipped one".to_string());
+    }
+    let simulated = simulate(replay);
+    let claimed_ticks = replay.ticks();
+    if let Some(desync) = simulated.desync {
+        Err(desync)
+    } else if !simulated.ended {
+        Err(format!(
+            "the run is still going after the last of its {} ticks",
+            claimed_ticks
+        ))
+    } else if simulated.ticks != claimed_ticks || simulated.distance != replay.distance {
+        Err(format!(
+            "claimed distance {:.2} after {} ticks, simulated distance {:.2} after {} ticks",
+            replay.distance, claimed_ticks, simulated.distance, simulated.ticks
+        ))
+    } else if replay.score.is_none() && replay.version >= SCORE_VERSION {
+        Err(format!(
+            "no claimed score in a version {} replay, simulated score {}",
+            replay.version, simulated.score
+        ))
+    } else if replay.score.is_some_and(|score| score != simulated.score) {
+        Err(format!(
+            "claimed score {}, simulated score {}",
+            replay.score.unwrap_or_default(),
+            simulated.score
+        ))
+    } else {
+        Ok(simulated)
+    }
+}
+
+// Replay files to verify, the ones inside directories included
+fn replay_files(paths: &[&str]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths.iter().map(Path::new) {
+        let entries = match fs::read_dir(path) {
+            Ok(val) => val,
+            Err(_) => {
+                files.push(path.to_path_buf());
+                continue;
+            }
+        };
+        let mut dir_files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.to_string_lossy().ends_with(REPLAY_EXTENSION))
+            .collect();
+        dir_files.sort();
+        files.extend(dir_files);
+    }
+    files
+}
+
+/// Verifies every replay, printing one line per replay, and returns the process exit code:
+/// zero when all of them check out. Unless `any_tuning` is set, the replays must have been
+/// recorded with the tuning shipped with the game.
+pub fn run(paths: &[&str], any_tuning: bool) -> i32 {
+    let shipped_tuning = Tuning::default();
+    let required_tuning = if any_tuning {
+        None
+    } else {
+        Some(&shipped_tuning)
+    };
+    let files = replay_files(paths);
+    let mut failures = 0;
+    for path in &files {
+        let result = Replay::load(path).and_then(|replay| verify(&replay, required_tuning));
+        match result {
+            Ok(simulated) => println!(
+                "ok    {}: score {}, distance {:.2} after {} ticks",
+                path.display(),
+                simulated.score,
+                simulated.distance,
+                simulated.ticks
+            ),
+            Err(error) => {
+                failures += 1;
+                println!("FAIL  {}: {}", path.display(), error);
+            }
+        }
+    }
+    if failures > 0 {
+        eprintln!(
+            "{} of {} replays failed verification",
+            failures,
+            files.len()
+        );
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::TickInput;
+
+    const SEED: u64 = 0x5eed;
+    /// More than any run without input lasts
+    const MAX_TICKS: u32 = 100_000;
+
+    // A run holding nothing until the obstacles take every life, recorded like the game does
+    fn recorded_run() -> Replay {
+        let tuning = Tuning::default();
+        let level = Level::new(SEED, &tuning.speed_curve);
+        let course = Course::new(&level, &tuning);
+        let mut runner = Runner::new(&course, &tuning);
+        let mut replay = Replay::new(SEED, tuning.clone());
+        while !runner.player.collided {
+            assert!(replay.ticks() < MAX_TICKS, "the run never ended");
+            let input = TickInput::default();
+            replay.push(input);
+            runner.step(input, &course, &tuning);
+            replay.push_hash(runner.state_hash());
+        }
+        replay.distance = runner.position.x - SPAWN_X;
+        replay.score = Some(runner.score.total());
+        replay
+    }
+
+    #[test]
+    fn recorded_run_passes() {
+        let replay = recorded_run();
+        let simulated = verify(&replay, Some(&Tuning::default())).unwrap();
+        assert_eq!(simulated.ticks, replay.ticks());
+        assert_eq!(Some(simulated.score), replay.score);
+    }
+
+    #[test]
+    fn tampered_distance_fails() {
+        let mut replay = recorded_run();
+        replay.distance += 1.0;
+        assert!(verify(&replay, Some(&Tuning::default())).is_err());
+    }
+
+    #[test]
+    fn tampered_score_fails() {
+        let mut replay = recorded_run();
+        replay.score = replay.score.map(|score| score + 1);
+        assert!(verify(&replay, Some(&Tuning::default())).is_err());
+    }
+
+    #[test]
+    fn missing_score_fails_unless_older_than_scores() {
+        let mut replay = recorded_run();
+        replay.score = None;
+        assert!(verify(&replay, Some(&Tuning::default())).is_err());
+        replay.version = SCORE_VERSION - 1;
+        assert!(verify(&replay, Some(&Tuning::default())).is_ok());
+    }
+
+    #[test]
+    fn other_tuning_fails_unless_allowed() {
+        let mut replay = recorded_run();
+        replay.tuning.lives = 9999;
+        assert!(verify(&replay, Some(&Tuning::default())).is_err());
+        // without a required tuning, the run is still checked against its own end
+        assert!(verify(&replay, None).is_err());
+    }
+
+    #[test]
+    fn invalid_tuning_fails() {
+        let mut replay = recorded_run();
+        replay.tuning.gravity = 0.0;
+        let error = verify(&replay, None).unwrap_err();
+        assert!(error.contains("gravity"), "{}", error);
+    }
+}